@@ -0,0 +1,361 @@
+use super::{StoreError, StoreResult};
+use crate::core::{
+    BenchmarkStats, ExecutionSettings, Job, JobOutput, JobStatus, JobTimings, Language, RunSample,
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+use tracing;
+
+/// Schema migrations, applied in order. The database's `user_version` records
+/// how many have run, so only append to this list; never edit an entry.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE jobs (
+        id TEXT PRIMARY KEY,
+        language TEXT NOT NULL,
+        source_file TEXT NOT NULL,
+        compile_cmd TEXT,
+        run_cmd TEXT NOT NULL,
+        is_compiled INTEGER NOT NULL,
+        source_code TEXT NOT NULL,
+        stdin TEXT NOT NULL,
+        expected_output TEXT NOT NULL,
+        cpu_time_limit REAL NOT NULL,
+        wall_time_limit REAL NOT NULL,
+        memory_limit INTEGER NOT NULL,
+        stack_limit INTEGER NOT NULL,
+        max_processes INTEGER NOT NULL,
+        max_file_size INTEGER NOT NULL,
+        enable_network INTEGER NOT NULL,
+        status_id INTEGER NOT NULL,
+        status TEXT NOT NULL,
+        stdout TEXT,
+        stderr TEXT,
+        compile_output TEXT,
+        message TEXT,
+        exit_code INTEGER,
+        time REAL,
+        memory INTEGER,
+        number_of_runs INTEGER NOT NULL,
+        benchmark INTEGER NOT NULL,
+        created_at INTEGER NOT NULL,
+        started_at INTEGER,
+        finished_at INTEGER
+    );
+    CREATE INDEX jobs_finished_at ON jobs (finished_at);
+    CREATE INDEX jobs_status_id ON jobs (status_id);
+    CREATE TABLE job_runs (
+        job_id TEXT NOT NULL REFERENCES jobs (id) ON DELETE CASCADE,
+        run INTEGER NOT NULL,
+        time REAL NOT NULL,
+        wall_time REAL NOT NULL,
+        memory INTEGER NOT NULL,
+        exit_code INTEGER NOT NULL,
+        PRIMARY KEY (job_id, run)
+    );",
+    "ALTER TABLE jobs ADD COLUMN queued_at_ms INTEGER;
+    ALTER TABLE jobs ADD COLUMN dequeued_at_ms INTEGER;
+    ALTER TABLE jobs ADD COLUMN compile_started_at_ms INTEGER;
+    ALTER TABLE jobs ADD COLUMN compile_finished_at_ms INTEGER;
+    ALTER TABLE jobs ADD COLUMN run_started_at_ms INTEGER;
+    ALTER TABLE jobs ADD COLUMN run_finished_at_ms INTEGER;
+    ALTER TABLE jobs ADD COLUMN finished_at_ms INTEGER;",
+    "ALTER TABLE jobs ADD COLUMN cached INTEGER NOT NULL DEFAULT 0;",
+];
+
+impl From<rusqlite::Error> for StoreError {
+    fn from(e: rusqlite::Error) -> Self {
+        StoreError::Backend(e.to_string())
+    }
+}
+
+/// Durable history of finished jobs in an embedded SQLite database.
+///
+/// The store backend is the hot path and may expire or lose results; the
+/// archive keeps every verdict so it can still be served afterwards.
+#[derive(Clone)]
+pub struct JobArchive {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl JobArchive {
+    pub fn open(path: impl AsRef<Path>) -> StoreResult<Self> {
+        let mut conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "foreign_keys", "ON")?;
+        // Zero what purges and deletes free instead of leaving it in free pages
+        conn.pragma_update(None, "secure_delete", "ON")?;
+        migrate(&mut conn)?;
+
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// Runs a query on the blocking pool so SQLite I/O stays off the async runtime.
+    async fn with_conn<T, F>(&self, f: F) -> StoreResult<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> StoreResult<T> + Send + 'static,
+    {
+        let conn = Arc::clone(&self.conn);
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock().unwrap();
+            f(&mut conn)
+        })
+        .await
+        .map_err(|e| StoreError::Backend(format!("Archive task failed: {}", e)))?
+    }
+
+    #[tracing::instrument(skip(self, job), fields(job_id = job.id), level = "debug")]
+    pub async fn archive_job(&self, job: &Job) -> StoreResult<()> {
+        let job = job.clone();
+        self.with_conn(move |conn| insert_job(conn, &job)).await
+    }
+
+    #[tracing::instrument(skip(self), level = "debug")]
+    pub async fn get_job(&self, id: &str) -> StoreResult<Option<Job>> {
+        let id = id.to_string();
+        self.with_conn(move |conn| load_job(conn, &id)).await
+    }
+
+    /// Erases the source, test data and output of an archived job, keeping
+    /// its verdict and timings, then checkpoints the WAL so the erased
+    /// values don't linger in it. Returns whether the job was found.
+    #[tracing::instrument(skip(self), level = "debug")]
+    pub async fn purge_job(&self, id: &str) -> StoreResult<bool> {
+        let id = id.to_string();
+        self.with_conn(move |conn| {
+            let updated = conn.execute(
+                "UPDATE jobs SET source_code = '', stdin = '', expected_output = '',
+                    stdout = NULL, stderr = NULL, compile_output = NULL
+                 WHERE id = ?1",
+                params![id],
+            )?;
+            if updated > 0 {
+                let busy: i64 = conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |row| row.get(0))?;
+                if busy != 0 {
+                    // A reader held the WAL; the next checkpoint overwrites it
+                    tracing::warn!("WAL checkpoint after purging job {} was blocked by a reader", id);
+                }
+            }
+            Ok(updated > 0)
+        })
+        .await
+    }
+
+    /// Deletes jobs that finished before `before` (unix seconds).
+    #[tracing::instrument(skip(self), level = "debug")]
+    pub async fn delete_finished_before(&self, before: i64) -> StoreResult<usize> {
+        self.with_conn(move |conn| {
+            Ok(conn.execute("DELETE FROM jobs WHERE finished_at < ?1", params![before])?)
+        })
+        .await
+    }
+}
+
+fn migrate(conn: &mut Connection) -> StoreResult<()> {
+    let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        tracing::info!("Applying archive migration {}", index + 1);
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", index + 1)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+fn encode_status(status: &JobStatus) -> StoreResult<String> {
+    serde_json::to_string(status).map_err(|e| StoreError::Serialization(e.to_string()))
+}
+
+fn decode_status(status: &str) -> StoreResult<JobStatus> {
+    serde_json::from_str(status).map_err(|e| StoreError::Serialization(e.to_string()))
+}
+
+fn insert_job(conn: &mut Connection, job: &Job) -> StoreResult<()> {
+    let id = job.id.to_string();
+    let tx = conn.transaction()?;
+
+    tx.execute(
+        "INSERT OR REPLACE INTO jobs (
+            id, language, source_file, compile_cmd, run_cmd, is_compiled,
+            source_code, stdin, expected_output,
+            cpu_time_limit, wall_time_limit, memory_limit, stack_limit,
+            max_processes, max_file_size, enable_network,
+            status_id, status, stdout, stderr, compile_output, message,
+            exit_code, time, memory, number_of_runs, benchmark,
+            created_at, started_at, finished_at,
+            queued_at_ms, dequeued_at_ms, compile_started_at_ms,
+            compile_finished_at_ms, run_started_at_ms, run_finished_at_ms,
+            finished_at_ms, cached
+        ) VALUES (
+            ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15,
+            ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26, ?27, ?28,
+            ?29, ?30, ?31, ?32, ?33, ?34, ?35, ?36, ?37, ?38
+        )",
+        params![
+            id,
+            job.language.name,
+            job.language.source_file,
+            job.language.compile_cmd,
+            job.language.run_cmd,
+            job.language.is_compiled,
+            job.source_code,
+            job.stdin,
+            job.expected_output,
+            job.settings.cpu_time_limit,
+            job.settings.wall_time_limit,
+            job.settings.memory_limit as i64,
+            job.settings.stack_limit as i64,
+            job.settings.max_processes,
+            job.settings.max_file_size as i64,
+            job.settings.enable_network,
+            job.status.id(),
+            encode_status(&job.status)?,
+            job.output.stdout,
+            job.output.stderr,
+            job.output.compile_output,
+            job.output.message,
+            job.output.exit_code,
+            job.output.time,
+            job.output.memory.map(|m| m as i64),
+            job.number_of_runs,
+            job.benchmark,
+            job.created_at,
+            job.started_at,
+            job.finished_at,
+            job.timings.queued_at,
+            job.timings.dequeued_at,
+            job.timings.compile_started_at,
+            job.timings.compile_finished_at,
+            job.timings.run_started_at,
+            job.timings.run_finished_at,
+            job.timings.finished_at,
+            job.cached,
+        ],
+    )?;
+
+    tx.execute("DELETE FROM job_runs WHERE job_id = ?1", params![id])?;
+    for (run, sample) in job.output.runs.iter().enumerate() {
+        tx.execute(
+            "INSERT INTO job_runs (job_id, run, time, wall_time, memory, exit_code)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                id,
+                run as i64,
+                sample.time,
+                sample.wall_time,
+                sample.memory as i64,
+                sample.exit_code,
+            ],
+        )?;
+    }
+
+    tx.commit()?;
+    Ok(())
+}
+
+fn load_job(conn: &mut Connection, id: &str) -> StoreResult<Option<Job>> {
+    let Ok(job_id) = id.parse::<u64>() else {
+        return Ok(None);
+    };
+
+    let row = conn
+        .query_row(
+            "SELECT language, source_file, compile_cmd, run_cmd, is_compiled,
+                    source_code, stdin, expected_output,
+                    cpu_time_limit, wall_time_limit, memory_limit, stack_limit,
+                    max_processes, max_file_size, enable_network,
+                    status, stdout, stderr, compile_output, message,
+                    exit_code, time, memory, number_of_runs, benchmark,
+                    created_at, started_at, finished_at,
+                    queued_at_ms, dequeued_at_ms, compile_started_at_ms,
+                    compile_finished_at_ms, run_started_at_ms, run_finished_at_ms,
+                    finished_at_ms, cached
+             FROM jobs WHERE id = ?1",
+            params![id],
+            |row| {
+                let job = Job {
+                    id: job_id,
+                    language: Language {
+                        name: row.get(0)?,
+                        source_file: row.get(1)?,
+                        compile_cmd: row.get(2)?,
+                        run_cmd: row.get(3)?,
+                        is_compiled: row.get(4)?,
+                    },
+                    source_code: row.get(5)?,
+                    stdin: row.get(6)?,
+                    expected_output: row.get(7)?,
+                    settings: ExecutionSettings {
+                        cpu_time_limit: row.get(8)?,
+                        wall_time_limit: row.get(9)?,
+                        memory_limit: row.get::<_, i64>(10)? as u64,
+                        stack_limit: row.get::<_, i64>(11)? as u64,
+                        max_processes: row.get(12)?,
+                        max_file_size: row.get::<_, i64>(13)? as u64,
+                        enable_network: row.get(14)?,
+                    },
+                    output: JobOutput {
+                        stdout: row.get(16)?,
+                        stderr: row.get(17)?,
+                        compile_output: row.get(18)?,
+                        message: row.get(19)?,
+                        exit_code: row.get(20)?,
+                        time: row.get(21)?,
+                        memory: row.get::<_, Option<i64>>(22)?.map(|m| m as u64),
+                        ..Default::default()
+                    },
+                    number_of_runs: row.get(23)?,
+                    benchmark: row.get(24)?,
+                    created_at: row.get(25)?,
+                    started_at: row.get(26)?,
+                    finished_at: row.get(27)?,
+                    timings: JobTimings {
+                        queued_at: row.get(28)?,
+                        dequeued_at: row.get(29)?,
+                        compile_started_at: row.get(30)?,
+                        compile_finished_at: row.get(31)?,
+                        run_started_at: row.get(32)?,
+                        run_finished_at: row.get(33)?,
+                        finished_at: row.get(34)?,
+                    },
+                    cached: row.get(35)?,
+                    ..Default::default()
+                };
+                let status: String = row.get(15)?;
+                Ok((job, status))
+            },
+        )
+        .optional()?;
+
+    let Some((mut job, status)) = row else {
+        return Ok(None);
+    };
+    job.status = decode_status(&status)?;
+
+    let mut stmt = conn.prepare(
+        "SELECT time, wall_time, memory, exit_code FROM job_runs
+         WHERE job_id = ?1 ORDER BY run",
+    )?;
+    job.output.runs = stmt
+        .query_map(params![id], |row| {
+            Ok(RunSample {
+                time: row.get(0)?,
+                wall_time: row.get(1)?,
+                memory: row.get::<_, i64>(2)? as u64,
+                exit_code: row.get(3)?,
+            })
+        })?
+        .collect::<Result<_, _>>()?;
+    if job.benchmark && !job.output.runs.is_empty() {
+        job.output.benchmark = Some(BenchmarkStats::from_samples(&job.output.runs));
+    }
+
+    Ok(Some(job))
+}
@@ -0,0 +1,66 @@
+use crate::core::metrics::metrics;
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Fails calls fast while a dependency is down, so callers don't each wait
+/// out a timeout. Opens after `threshold` failures in a row and stays open
+/// for `cooldown`; after that calls go through again, and the first to fail
+/// opens it for another cooldown while the first to succeed closes it.
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Default)]
+struct BreakerState {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    /// A `threshold` of 0 never opens.
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            threshold,
+            cooldown,
+            state: Mutex::new(BreakerState::default()),
+        }
+    }
+
+    /// Whether a call may go ahead.
+    pub fn allow(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.open_until.is_none_or(|until| Instant::now() >= until)
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.failures = 0;
+        if state.open_until.take().is_some() {
+            tracing::info!("Redis reachable again, closing circuit breaker");
+            metrics().redis_breaker_open.set(0);
+        }
+    }
+
+    pub fn record_failure(&self) {
+        if self.threshold == 0 {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.failures = state.failures.saturating_add(1);
+        if state.failures >= self.threshold {
+            if state.open_until.is_none() {
+                tracing::error!(
+                    "Redis failed {} times in a row, failing commands fast for {:?}",
+                    state.failures,
+                    self.cooldown
+                );
+                metrics().redis_breaker_open.set(1);
+            }
+            state.open_until = Some(Instant::now() + self.cooldown);
+        }
+    }
+}
@@ -0,0 +1,499 @@
+//! Versioned encoding of jobs for the Redis backend.
+//!
+//! Every stored job is wrapped in an envelope: the `FLJ` magic, a version
+//! byte and the payload. The current version encodes the payload as JSON,
+//! which tolerates added fields in both directions (missing fields take their
+//! defaults, unknown fields are ignored), so adding a field to `Job` does not
+//! need a new version. Bump `CURRENT_VERSION` only for changes JSON cannot
+//! absorb, such as renaming or retyping a field, and add an upgrade from the
+//! previous version.
+//!
+//! Entries written before the envelope existed are raw bincode of the `Job`
+//! struct. They are decoded with frozen copies of the old layouts in
+//! [`legacy`] and upgraded on read.
+
+use super::{QueueEntry, StoreError, StoreResult};
+use crate::core::Job;
+use bincode::Options;
+
+const MAGIC: &[u8; 3] = b"FLJ";
+
+/// JSON payload. Versions 0 and 1 are the unversioned bincode layouts.
+pub const CURRENT_VERSION: u8 = 2;
+
+pub fn encode_job(job: &Job) -> StoreResult<Vec<u8>> {
+    let mut data = Vec::with_capacity(512);
+    data.extend_from_slice(MAGIC);
+    data.push(CURRENT_VERSION);
+    serde_json::to_writer(&mut data, job).map_err(|e| {
+        tracing::error!("Failed to serialize job: {:?}", e);
+        StoreError::Serialization(e.to_string())
+    })?;
+    Ok(data)
+}
+
+pub fn decode_job(data: &[u8]) -> StoreResult<Job> {
+    if let Some(payload) = data.strip_prefix(MAGIC.as_slice()) {
+        match payload.split_first() {
+            Some((&CURRENT_VERSION, payload)) => {
+                return serde_json::from_slice(payload).map_err(|e| {
+                    tracing::error!("Failed to deserialize job: {:?}", e);
+                    StoreError::Serialization(e.to_string())
+                });
+            }
+            Some((&version, _)) if version > CURRENT_VERSION => {
+                return Err(StoreError::Serialization(format!(
+                    "Job encoded with unknown version {}",
+                    version
+                )));
+            }
+            // A legacy entry that happens to start with the magic bytes
+            _ => {}
+        }
+    }
+
+    legacy::decode(data).ok_or_else(|| {
+        tracing::error!("Failed to deserialize job: no known encoding matched");
+        StoreError::Serialization("Unrecognized job encoding".to_string())
+    })
+}
+
+/// Queue entries are plain JSON; they never carried a version.
+pub fn encode_entry(entry: &QueueEntry) -> StoreResult<Vec<u8>> {
+    serde_json::to_vec(entry).map_err(|e| StoreError::Serialization(e.to_string()))
+}
+
+/// Returns `None` for anything that isn't an entry, such as the full jobs
+/// that were queued before entries existed.
+pub fn decode_entry(data: &[u8]) -> Option<QueueEntry> {
+    serde_json::from_slice::<QueueEntry>(data)
+        .ok()
+        .filter(|entry| !entry.key.is_empty())
+}
+
+/// Layouts of `Job` as it was stored before the envelope, frozen so they keep
+/// decoding however the live types change.
+mod legacy {
+    use super::*;
+    use crate::core::{
+        BenchmarkStats, ExecutionSettings, JobOutput, JobStatus, Language, RunSample, Summary,
+    };
+    use serde::Deserialize;
+
+    /// bincode 1.x defaults as used by `bincode::deserialize`, but rejecting
+    /// trailing bytes so a payload only matches the layout it was written with.
+    pub fn decode(data: &[u8]) -> Option<Job> {
+        let options = bincode::options()
+            .with_fixint_encoding()
+            .reject_trailing_bytes();
+
+        if let Ok(job) = options.deserialize::<JobV1>(data) {
+            return Some(job.into());
+        }
+        options
+            .deserialize::<JobV0>(data)
+            .ok()
+            .map(|job| JobV1::from(job).into())
+    }
+
+    #[derive(Deserialize)]
+    struct LanguageV0 {
+        name: String,
+        source_file: String,
+        compile_cmd: Option<String>,
+        run_cmd: String,
+        is_compiled: bool,
+    }
+
+    #[derive(Deserialize)]
+    struct SettingsV0 {
+        cpu_time_limit: f64,
+        wall_time_limit: f64,
+        memory_limit: u64,
+        stack_limit: u64,
+        max_processes: u32,
+        max_file_size: u64,
+        enable_network: bool,
+    }
+
+    #[derive(Deserialize)]
+    enum StatusV0 {
+        Queued,
+        Processing,
+        Accepted,
+        WrongAnswer,
+        TimeLimitExceeded,
+        CompilationError,
+        RuntimeError(String),
+        InternalError,
+        ExecFormatError,
+    }
+
+    #[derive(Deserialize)]
+    struct OutputV0 {
+        stdout: Option<String>,
+        stderr: Option<String>,
+        compile_output: Option<String>,
+        time: Option<f64>,
+        memory: Option<u64>,
+        exit_code: Option<i32>,
+        message: Option<String>,
+    }
+
+    /// Original layout.
+    #[derive(Deserialize)]
+    struct JobV0 {
+        id: u64,
+        source_code: String,
+        language: LanguageV0,
+        stdin: String,
+        expected_output: String,
+        settings: SettingsV0,
+        status: StatusV0,
+        created_at: i64,
+        started_at: Option<i64>,
+        finished_at: Option<i64>,
+        output: OutputV0,
+        number_of_runs: u8,
+    }
+
+    #[derive(Deserialize)]
+    struct RunSampleV1 {
+        time: f64,
+        wall_time: f64,
+        memory: u64,
+        exit_code: i32,
+    }
+
+    #[derive(Deserialize)]
+    struct SummaryV1 {
+        min: f64,
+        median: f64,
+        mean: f64,
+        stddev: f64,
+    }
+
+    #[derive(Deserialize)]
+    struct StatsV1 {
+        cpu_time: SummaryV1,
+        wall_time: SummaryV1,
+        memory: SummaryV1,
+    }
+
+    #[derive(Deserialize)]
+    struct OutputV1 {
+        stdout: Option<String>,
+        stderr: Option<String>,
+        compile_output: Option<String>,
+        time: Option<f64>,
+        memory: Option<u64>,
+        exit_code: Option<i32>,
+        message: Option<String>,
+        runs: Vec<RunSampleV1>,
+        benchmark: Option<StatsV1>,
+    }
+
+    /// Adds benchmark runs and statistics.
+    #[derive(Deserialize)]
+    struct JobV1 {
+        id: u64,
+        source_code: String,
+        language: LanguageV0,
+        stdin: String,
+        expected_output: String,
+        settings: SettingsV0,
+        status: StatusV0,
+        created_at: i64,
+        started_at: Option<i64>,
+        finished_at: Option<i64>,
+        output: OutputV1,
+        number_of_runs: u8,
+        benchmark: bool,
+    }
+
+    impl From<JobV0> for JobV1 {
+        fn from(job: JobV0) -> Self {
+            Self {
+                id: job.id,
+                source_code: job.source_code,
+                language: job.language,
+                stdin: job.stdin,
+                expected_output: job.expected_output,
+                settings: job.settings,
+                status: job.status,
+                created_at: job.created_at,
+                started_at: job.started_at,
+                finished_at: job.finished_at,
+                output: OutputV1 {
+                    stdout: job.output.stdout,
+                    stderr: job.output.stderr,
+                    compile_output: job.output.compile_output,
+                    time: job.output.time,
+                    memory: job.output.memory,
+                    exit_code: job.output.exit_code,
+                    message: job.output.message,
+                    runs: Vec::new(),
+                    benchmark: None,
+                },
+                number_of_runs: job.number_of_runs,
+                benchmark: false,
+            }
+        }
+    }
+
+    impl From<LanguageV0> for Language {
+        fn from(language: LanguageV0) -> Self {
+            Self {
+                name: language.name,
+                source_file: language.source_file,
+                compile_cmd: language.compile_cmd,
+                run_cmd: language.run_cmd,
+                is_compiled: language.is_compiled,
+            }
+        }
+    }
+
+    impl From<SettingsV0> for ExecutionSettings {
+        fn from(settings: SettingsV0) -> Self {
+            Self {
+                cpu_time_limit: settings.cpu_time_limit,
+                wall_time_limit: settings.wall_time_limit,
+                memory_limit: settings.memory_limit,
+                stack_limit: settings.stack_limit,
+                max_processes: settings.max_processes,
+                max_file_size: settings.max_file_size,
+                enable_network: settings.enable_network,
+            }
+        }
+    }
+
+    impl From<StatusV0> for JobStatus {
+        fn from(status: StatusV0) -> Self {
+            match status {
+                StatusV0::Queued => JobStatus::Queued,
+                StatusV0::Processing => JobStatus::Processing,
+                StatusV0::Accepted => JobStatus::Accepted,
+                StatusV0::WrongAnswer => JobStatus::WrongAnswer,
+                StatusV0::TimeLimitExceeded => JobStatus::TimeLimitExceeded,
+                StatusV0::CompilationError => JobStatus::CompilationError,
+                StatusV0::RuntimeError(e) => JobStatus::RuntimeError(e),
+                StatusV0::InternalError => JobStatus::InternalError,
+                StatusV0::ExecFormatError => JobStatus::ExecFormatError,
+            }
+        }
+    }
+
+    impl From<SummaryV1> for Summary {
+        fn from(summary: SummaryV1) -> Self {
+            Self {
+                min: summary.min,
+                median: summary.median,
+                mean: summary.mean,
+                stddev: summary.stddev,
+            }
+        }
+    }
+
+    impl From<JobV1> for Job {
+        fn from(job: JobV1) -> Self {
+            Self {
+                id: job.id,
+                source_code: job.source_code,
+                language: job.language.into(),
+                stdin: job.stdin,
+                expected_output: job.expected_output,
+                settings: job.settings.into(),
+                status: job.status.into(),
+                created_at: job.created_at,
+                started_at: job.started_at,
+                finished_at: job.finished_at,
+                output: JobOutput {
+                    stdout: job.output.stdout,
+                    stderr: job.output.stderr,
+                    compile_output: job.output.compile_output,
+                    time: job.output.time,
+                    memory: job.output.memory,
+                    exit_code: job.output.exit_code,
+                    message: job.output.message,
+                    runs: job
+                        .output
+                        .runs
+                        .into_iter()
+                        .map(|run| RunSample {
+                            time: run.time,
+                            wall_time: run.wall_time,
+                            memory: run.memory,
+                            exit_code: run.exit_code,
+                        })
+                        .collect(),
+                    benchmark: job.output.benchmark.map(|stats| BenchmarkStats {
+                        cpu_time: stats.cpu_time.into(),
+                        wall_time: stats.wall_time.into(),
+                        memory: stats.memory.into(),
+                    }),
+                },
+                number_of_runs: job.number_of_runs,
+                benchmark: job.benchmark,
+                ..Default::default()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{JobStatus, Language, Priority};
+
+    type LanguageV0 = (String, String, Option<String>, String, bool);
+    type SettingsV0 = (f64, f64, u64, u64, u32, u64, bool);
+    type OutputV0 = (
+        Option<String>,
+        Option<String>,
+        Option<String>,
+        Option<f64>,
+        Option<u64>,
+        Option<i32>,
+        Option<String>,
+    );
+
+    /// Legacy layouts are written field by field, as bincode lays out
+    /// structs, so the tests don't depend on the frozen types.
+    fn language_v0() -> LanguageV0 {
+        let cpp = Language::from_name("cpp").unwrap();
+        (cpp.name, cpp.source_file, cpp.compile_cmd, cpp.run_cmd, cpp.is_compiled)
+    }
+
+    fn settings_v0() -> SettingsV0 {
+        (2.0, 5.0, 128_000, 64_000, 4, 1024, false)
+    }
+
+    fn output_v0() -> OutputV0 {
+        (Some("1\n".to_string()), None, Some(String::new()), Some(0.25), Some(2048), Some(0), None)
+    }
+
+    fn legacy_bytes(value: &impl serde::Serialize) -> Vec<u8> {
+        bincode::options()
+            .with_fixint_encoding()
+            .serialize(value)
+            .unwrap()
+    }
+
+    #[test]
+    fn round_trips_current_version() {
+        let mut job = Job::new("int main() {}".to_string(), Language::from_name("cpp").unwrap());
+        job.stdin = "1 2".to_string();
+        job.status = JobStatus::RuntimeError("SIGSEGV".to_string());
+        job.priority = Priority::High;
+        job.tenant = "acme".to_string();
+        job.deadline = Some(1_700_000_000_000);
+        job.trace_context.insert("traceparent".to_string(), "00-abc-def-01".to_string());
+
+        let data = encode_job(&job).unwrap();
+        assert_eq!(&data[..3], MAGIC);
+        assert_eq!(data[3], CURRENT_VERSION);
+
+        let decoded = decode_job(&data).unwrap();
+        assert_eq!(serde_json::to_value(&decoded).unwrap(), serde_json::to_value(&job).unwrap());
+    }
+
+    #[test]
+    fn decodes_v0() {
+        // RuntimeError is variant 6 of the original status enum
+        let status = (6u32, "SIGFPE".to_string());
+        let job = (
+            42u64,
+            "int main() {}".to_string(),
+            language_v0(),
+            "1".to_string(),
+            "2".to_string(),
+            settings_v0(),
+            status,
+            1_600_000_000i64,
+            Some(1_600_000_001i64),
+            Some(1_600_000_002i64),
+            output_v0(),
+            1u8,
+        );
+
+        let decoded = decode_job(&legacy_bytes(&job)).unwrap();
+        assert_eq!(decoded.id, 42);
+        assert_eq!(decoded.language.name, "cpp");
+        assert_eq!(decoded.expected_output, "2");
+        assert_eq!(decoded.settings.max_processes, 4);
+        assert_eq!(decoded.status, JobStatus::RuntimeError("SIGFPE".to_string()));
+        assert_eq!(decoded.finished_at, Some(1_600_000_002));
+        assert_eq!(decoded.output.stdout.as_deref(), Some("1\n"));
+        assert!(decoded.output.runs.is_empty());
+        assert!(!decoded.benchmark);
+        assert_eq!(decoded.priority, Priority::Normal);
+    }
+
+    #[test]
+    fn decodes_v1() {
+        let (stdout, stderr, compile_output, time, memory, exit_code, message) = output_v0();
+        let runs = vec![(0.25f64, 0.5f64, 2048u64, 0i32), (0.75, 1.0, 4096, 0)];
+        let summary = (0.25f64, 0.5f64, 0.5f64, 0.25f64);
+        let stats = Some((summary, summary, summary));
+        let output = (stdout, stderr, compile_output, time, memory, exit_code, message, runs, stats);
+        // Accepted is variant 2
+        let job = (
+            7u64,
+            "int main() {}".to_string(),
+            language_v0(),
+            String::new(),
+            String::new(),
+            settings_v0(),
+            2u32,
+            1_600_000_000i64,
+            None::<i64>,
+            None::<i64>,
+            output,
+            2u8,
+            true,
+        );
+
+        let decoded = decode_job(&legacy_bytes(&job)).unwrap();
+        assert_eq!(decoded.id, 7);
+        assert_eq!(decoded.status, JobStatus::Accepted);
+        assert_eq!(decoded.number_of_runs, 2);
+        assert!(decoded.benchmark);
+        assert_eq!(decoded.output.runs.len(), 2);
+        assert_eq!(decoded.output.runs[1].memory, 4096);
+        assert_eq!(decoded.output.benchmark.unwrap().wall_time.median, 0.5);
+    }
+
+    #[test]
+    fn rejects_unknown_version() {
+        let mut data = MAGIC.to_vec();
+        data.push(CURRENT_VERSION + 1);
+        data.extend_from_slice(b"{}");
+
+        match decode_job(&data) {
+            Err(StoreError::Serialization(message)) => assert!(message.contains("unknown version")),
+            other => panic!("expected a serialization error, got {:?}", other.map(|job| job.id)),
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        for data in [&b""[..], b"FL", b"FLJ", b"FLJ\x02", b"FLJ\x02{\"id\":"] {
+            assert!(
+                matches!(decode_job(data), Err(StoreError::Serialization(_))),
+                "decoded {:?}",
+                data
+            );
+        }
+    }
+
+    #[test]
+    fn decodes_entries_and_skips_jobs() {
+        let job = Job::new(String::new(), Language::from_name("python").unwrap());
+        let entry = QueueEntry::new("123", &job);
+
+        let decoded = decode_entry(&encode_entry(&entry).unwrap()).unwrap();
+        assert_eq!(decoded.key, "123");
+        assert_eq!(decoded.language, "python");
+        assert!(decode_entry(&encode_job(&job).unwrap()).is_none());
+    }
+}
@@ -0,0 +1,354 @@
+use super::{
+    Claim, JobQueue, JobStore, QueueChoice, QueueEntry, ResultCache, StoreResult, WorkerRegistry, CLAIM_TTL,
+};
+use crate::core::{language_queue, unix_millis, Job, WorkerInfo};
+use async_trait::async_trait;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::Notify;
+
+/// How long `get_job_from_queue` waits for a push when the queues are empty
+/// before returning `None`. Like the Redis pop, which moves an entry into the
+/// processing hash or returns nothing without blocking, it leaves polling to
+/// the worker, which asks again after `None`; the wait only saves spinning.
+const QUEUE_POLL_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Entries a worker has popped and not acked, by job key, with the queue
+/// each came from.
+type Held = HashMap<String, (String, QueueEntry)>;
+
+struct StoredJob {
+    job: Job,
+    expires_at: Option<Instant>,
+}
+
+/// In-process job store and queue, for running flash without Redis and for tests.
+/// State is lost when the process exits.
+#[derive(Clone, Default)]
+pub struct MemoryClient {
+    jobs: Arc<Mutex<HashMap<String, StoredJob>>>,
+    finished: Arc<Mutex<HashMap<String, i64>>>,
+    queues: Arc<Mutex<HashMap<String, VecDeque<QueueEntry>>>>,
+    /// Woken when an entry is pushed onto any queue.
+    pushed: Arc<Notify>,
+    /// Entries each worker holds, by worker.
+    processing: Arc<Mutex<HashMap<String, Held>>>,
+    workers: Arc<Mutex<HashMap<String, WorkerInfo>>>,
+    /// Cached results by fingerprint, with when they expire.
+    results: Arc<Mutex<HashMap<String, (Job, Instant)>>>,
+    /// Fingerprints of running jobs, with when their claim expires.
+    claims: Arc<Mutex<HashMap<String, Instant>>>,
+    /// Keys of the jobs waiting on each fingerprint's run.
+    waiting: Arc<Mutex<HashMap<String, Vec<String>>>>,
+}
+
+impl MemoryClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn push(&self, queue: &str, entry: QueueEntry) {
+        self.queues
+            .lock()
+            .unwrap()
+            .entry(queue.to_string())
+            .or_default()
+            .push_back(entry);
+        self.pushed.notify_one();
+    }
+
+    /// Takes the next entry as the Redis backend's pop script does, into
+    /// `worker`'s processing set.
+    fn pop(&self, worker: &str, queues: &[QueueChoice], promote_before: i64) -> Option<QueueEntry> {
+        let mut items = self.queues.lock().unwrap();
+        let promoted = queues
+            .iter()
+            .skip(1)
+            .filter_map(|queue| Some((queue, items.get(&queue.name)?.front()?.queued_at)))
+            .filter(|(_, queued_at)| *queued_at < promote_before)
+            .min_by_key(|(queue, queued_at)| (queue.turn, *queued_at))
+            .map(|(queue, _)| queue.name.as_str());
+
+        let name = promoted.or_else(|| {
+            queues
+                .iter()
+                .map(|queue| queue.name.as_str())
+                .find(|name| items.get(*name).is_some_and(|queue| !queue.is_empty()))
+        })?;
+        let entry = items.get_mut(name)?.pop_front()?;
+        self.processing
+            .lock()
+            .unwrap()
+            .entry(worker.to_string())
+            .or_default()
+            .insert(entry.key.clone(), (name.to_string(), entry.clone()));
+        Some(entry)
+    }
+
+    fn insert(&self, key: &str, job: &Job, ttl: Option<Duration>) {
+        if let Some(finished_at) = job.finished_at {
+            self.finished.lock().unwrap().insert(key.to_string(), finished_at);
+        }
+        self.jobs.lock().unwrap().insert(
+            key.to_string(),
+            StoredJob {
+                job: job.clone(),
+                expires_at: ttl.map(|ttl| Instant::now() + ttl),
+            },
+        );
+    }
+}
+
+#[async_trait]
+impl JobStore for MemoryClient {
+    async fn store_job(&self, key: &str, job: &Job, ttl: Option<Duration>) -> StoreResult<()> {
+        self.insert(key, job, ttl);
+        Ok(())
+    }
+
+    async fn get_job(&self, key: &str) -> StoreResult<Option<Job>> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let expired = match jobs.get(key) {
+            Some(stored) => stored.expires_at.is_some_and(|at| at <= Instant::now()),
+            None => return Ok(None),
+        };
+
+        if expired {
+            jobs.remove(key);
+            return Ok(None);
+        }
+        Ok(jobs.get(key).map(|stored| stored.job.clone()))
+    }
+
+    async fn ping(&self) -> StoreResult<()> {
+        Ok(())
+    }
+
+    async fn sweep_expired(&self, before: i64) -> StoreResult<usize> {
+        // Expired jobs are otherwise only dropped when read
+        let now = Instant::now();
+        self.jobs
+            .lock()
+            .unwrap()
+            .retain(|_, stored| stored.expires_at.is_none_or(|at| at > now));
+
+        let mut finished = self.finished.lock().unwrap();
+        let before_len = finished.len();
+        finished.retain(|_, finished_at| *finished_at >= before);
+        Ok(before_len - finished.len())
+    }
+
+    async fn finished_since(&self, since: i64) -> StoreResult<Vec<(String, i64)>> {
+        let mut finished: Vec<(String, i64)> = self
+            .finished
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, finished_at)| **finished_at >= since)
+            .map(|(key, finished_at)| (key.clone(), *finished_at))
+            .collect();
+        finished.sort_by_key(|(_, finished_at)| *finished_at);
+        Ok(finished)
+    }
+}
+
+#[async_trait]
+impl JobQueue for MemoryClient {
+    async fn create_job(&self, key: &str, queue: &str, job: &Job) -> StoreResult<()> {
+        self.insert(key, job, None);
+        self.push(queue, QueueEntry::new(key, job));
+        Ok(())
+    }
+
+    async fn get_job_from_queue(
+        &self,
+        worker: &str,
+        queues: &[QueueChoice],
+        max_wait: Option<Duration>,
+    ) -> StoreResult<Option<Job>> {
+        let promote_before = match max_wait {
+            Some(max_wait) => unix_millis() - max_wait.as_millis() as i64,
+            None => i64::MIN,
+        };
+
+        let mut entry = self.pop(worker, queues, promote_before);
+        if entry.is_none() {
+            let _ = tokio::time::timeout(QUEUE_POLL_TIMEOUT, self.pushed.notified()).await;
+            entry = self.pop(worker, queues, promote_before);
+        }
+        let Some(entry) = entry else {
+            return Ok(None);
+        };
+
+        match self.get_job(&entry.key).await? {
+            Some(job) if !job.is_finished() => Ok(Some(job)),
+            _ => {
+                // Missing, or finished by a worker that died before acking it
+                self.ack_job(worker, &entry.key).await?;
+                Ok(None)
+            }
+        }
+    }
+
+    async fn ack_job(&self, worker: &str, key: &str) -> StoreResult<()> {
+        let mut processing = self.processing.lock().unwrap();
+        if let Some(held) = processing.get_mut(worker) {
+            held.remove(key);
+            if held.is_empty() {
+                processing.remove(worker);
+            }
+        }
+        Ok(())
+    }
+
+    async fn processing_jobs(&self, worker: &str) -> StoreResult<Vec<String>> {
+        let processing = self.processing.lock().unwrap();
+        let keys = processing.get(worker).map(|held| held.keys().cloned().collect());
+        Ok(keys.unwrap_or_default())
+    }
+
+    async fn requeue_processing(&self, worker: &str, keys: Option<&[String]>) -> StoreResult<usize> {
+        let taken: Vec<(String, QueueEntry)> = {
+            let mut processing = self.processing.lock().unwrap();
+            let Some(held) = processing.get_mut(worker) else {
+                return Ok(0);
+            };
+            let taken = match keys {
+                Some(keys) => keys.iter().filter_map(|key| held.remove(key)).collect(),
+                None => held.drain().map(|(_, popped)| popped).collect(),
+            };
+            if held.is_empty() {
+                processing.remove(worker);
+            }
+            taken
+        };
+
+        let mut queues = self.queues.lock().unwrap();
+        for (queue, entry) in &taken {
+            queues.entry(queue.clone()).or_default().push_front(entry.clone());
+            self.pushed.notify_one();
+        }
+        Ok(taken.len())
+    }
+
+    async fn route_by_language(&self, queue: &str, languages: &[&str]) -> StoreResult<usize> {
+        let mut queues = self.queues.lock().unwrap();
+        let Some(entries) = queues.remove(queue) else {
+            return Ok(0);
+        };
+        let (routed, kept): (VecDeque<QueueEntry>, VecDeque<QueueEntry>) = entries
+            .into_iter()
+            .partition(|entry| languages.contains(&entry.language.as_str()));
+        if !kept.is_empty() {
+            queues.insert(queue.to_string(), kept);
+        }
+        // Last first, so the first ends up at the front
+        for entry in routed.iter().rev() {
+            let target = queues.entry(language_queue(queue, &entry.language)).or_default();
+            target.push_front(entry.clone());
+            self.pushed.notify_one();
+        }
+        Ok(routed.len())
+    }
+
+    async fn queue_lens(&self, queues: &[String]) -> StoreResult<Vec<usize>> {
+        let items = self.queues.lock().unwrap();
+        Ok(queues
+            .iter()
+            .map(|queue| items.get(queue).map_or(0, VecDeque::len))
+            .collect())
+    }
+}
+
+#[async_trait]
+impl WorkerRegistry for MemoryClient {
+    async fn heartbeat(&self, worker: &WorkerInfo) -> StoreResult<()> {
+        self.workers.lock().unwrap().insert(worker.id.clone(), worker.clone());
+        Ok(())
+    }
+
+    async fn list_workers(&self) -> StoreResult<Vec<WorkerInfo>> {
+        let workers = self.workers.lock().unwrap().values().cloned().collect();
+        Ok(workers)
+    }
+
+    async fn remove_worker(&self, id: &str) -> StoreResult<Option<WorkerInfo>> {
+        let removed = self.workers.lock().unwrap().remove(id);
+        Ok(removed)
+    }
+}
+
+#[async_trait]
+impl ResultCache for MemoryClient {
+    async fn claim(&self, fingerprint: &str, key: &str) -> StoreResult<Claim> {
+        {
+            let mut results = self.results.lock().unwrap();
+            match results.get(fingerprint) {
+                Some((job, expires_at)) if Instant::now() < *expires_at => {
+                    return Ok(Claim::Hit(Box::new(job.clone())));
+                }
+                Some(_) => {
+                    results.remove(fingerprint);
+                }
+                None => {}
+            }
+        }
+        let mut claims = self.claims.lock().unwrap();
+        let now = Instant::now();
+        match claims.get(fingerprint) {
+            Some(claimed_until) if now < *claimed_until => {
+                self.waiting
+                    .lock()
+                    .unwrap()
+                    .entry(fingerprint.to_string())
+                    .or_default()
+                    .push(key.to_string());
+                Ok(Claim::Waiting)
+            }
+            _ => {
+                claims.insert(fingerprint.to_string(), now + CLAIM_TTL);
+                Ok(Claim::Leader)
+            }
+        }
+    }
+
+    async fn settle(
+        &self,
+        fingerprint: &str,
+        result: Option<&Job>,
+        ttl: Duration,
+    ) -> StoreResult<Vec<String>> {
+        if let Some(job) = result {
+            self.results
+                .lock()
+                .unwrap()
+                .insert(fingerprint.to_string(), (job.clone(), Instant::now() + ttl));
+        }
+        self.claims.lock().unwrap().remove(fingerprint);
+        let waiting = self.waiting.lock().unwrap().remove(fingerprint);
+        Ok(waiting.unwrap_or_default())
+    }
+
+    async fn forget(&self, fingerprint: &str) -> StoreResult<()> {
+        self.results.lock().unwrap().remove(fingerprint);
+        Ok(())
+    }
+
+    async fn take_orphans(&self) -> StoreResult<Vec<String>> {
+        let claims = self.claims.lock().unwrap();
+        let now = Instant::now();
+        let mut waiting = self.waiting.lock().unwrap();
+        let mut orphans = Vec::new();
+        waiting.retain(|fingerprint, keys| {
+            let claimed = claims.get(fingerprint).is_some_and(|until| now < *until);
+            if !claimed {
+                orphans.append(keys);
+            }
+            claimed
+        });
+        Ok(orphans)
+    }
+}
@@ -1 +1,335 @@
+pub mod archive;
+pub mod breaker;
+pub mod codec;
+pub mod memory;
 pub mod redis;
+
+use crate::core::{unix_millis, Job, WorkerInfo};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::{fmt, time::Duration};
+
+/// Sorted index of finished job ids, scored by `finished_at`.
+pub const FINISHED_INDEX: &str = "jobs:finished";
+
+/// Hash of worker id to the worker's [`WorkerInfo`].
+pub const WORKERS_KEY: &str = "workers";
+
+/// How long a job's claim on its fingerprint lasts. A run that hasn't
+/// settled by then is presumed lost, and [`ResultCache::take_orphans`]
+/// hands its waiters back to be run on their own.
+pub const CLAIM_TTL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug)]
+pub enum StoreError {
+    Backend(String),
+    Serialization(String),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Backend(e) => write!(f, "Backend error: {}", e),
+            StoreError::Serialization(e) => write!(f, "Serialization error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+pub type StoreResult<T> = Result<T, StoreError>;
+
+/// What the job queue carries: the key the job is stored under, plus what a
+/// worker needs to route it without loading the job.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QueueEntry {
+    pub key: String,
+    pub language: String,
+    /// When the job was submitted, in milliseconds since the epoch. Used to
+    /// find lower priority entries that have waited too long.
+    pub queued_at: i64,
+}
+
+impl QueueEntry {
+    pub fn new(key: &str, job: &Job) -> Self {
+        Self {
+            key: key.to_string(),
+            language: job.language.name.clone(),
+            queued_at: job.timings.queued_at.unwrap_or_else(unix_millis),
+        }
+    }
+}
+
+/// A queue to pop from, with its tenant's turn in the scheduler's order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueueChoice {
+    pub name: String,
+    /// Lower goes first. Queues of one tenant share a turn.
+    pub turn: usize,
+}
+
+/// Key-value storage for job state and results.
+#[async_trait]
+pub trait JobStore: Send + Sync {
+    /// Checks that the store is reachable.
+    async fn ping(&self) -> StoreResult<()>;
+
+    async fn store_job(&self, key: &str, job: &Job, ttl: Option<Duration>) -> StoreResult<()>;
+
+    async fn get_job(&self, key: &str) -> StoreResult<Option<Job>>;
+
+    /// Drops finished-index entries for jobs that finished before `before`
+    /// (unix seconds), whose results have expired. Returns how many were removed.
+    async fn sweep_expired(&self, before: i64) -> StoreResult<usize>;
+
+    /// Keys of jobs that finished at or after `since` (unix seconds), with
+    /// their finish times, oldest first.
+    async fn finished_since(&self, since: i64) -> StoreResult<Vec<(String, i64)>>;
+}
+
+/// Work queue that hands submitted jobs to workers.
+#[async_trait]
+pub trait JobQueue: Send + Sync {
+    /// Stores the job under `key` and pushes a [`QueueEntry`] for it onto `queue`.
+    async fn create_job(&self, key: &str, queue: &str, job: &Job) -> StoreResult<()>;
+
+    /// Pops the oldest entry of the first non-empty of `queues`, which are
+    /// given highest priority first, and loads its job from the store. An
+    /// entry that has waited longer than `max_wait` at the head of a later
+    /// queue is taken first, so lower priorities aren't starved; `None`
+    /// serves priorities strictly. Of several such entries, the one of the
+    /// tenant with the earliest turn is taken, the longest waiting within
+    /// it, so tenants keep taking turns while their jobs starve. Returns
+    /// `None` when the queues are empty or the job no longer exists or
+    /// already finished.
+    ///
+    /// The entry moves into `worker`'s processing set in the same step, and
+    /// stays there until [`JobQueue::ack_job`], so it can be put back if the
+    /// worker dies holding it.
+    async fn get_job_from_queue(
+        &self,
+        worker: &str,
+        queues: &[QueueChoice],
+        max_wait: Option<Duration>,
+    ) -> StoreResult<Option<Job>>;
+
+    /// Drops `key` from `worker`'s processing set, once its job has a final
+    /// result or was queued again.
+    async fn ack_job(&self, worker: &str, key: &str) -> StoreResult<()>;
+
+    /// Keys of the jobs in `worker`'s processing set.
+    async fn processing_jobs(&self, worker: &str) -> StoreResult<Vec<String>>;
+
+    /// Moves entries of `worker`'s processing set back to the front of the
+    /// queues they were popped from, all of them if `keys` is `None`.
+    /// Each entry is put back once even if several callers race. Returns
+    /// how many were.
+    async fn requeue_processing(&self, worker: &str, keys: Option<&[String]>) -> StoreResult<usize>;
+
+    /// Moves the entries of `queue`, from before jobs were routed by
+    /// language, to the front of their languages' queues, keeping their
+    /// order. Entries in other languages than `languages` stay. Returns how
+    /// many moved.
+    async fn route_by_language(&self, queue: &str, languages: &[&str]) -> StoreResult<usize>;
+
+    /// Number of entries waiting in each of `queues`, read in one round trip.
+    async fn queue_lens(&self, queues: &[String]) -> StoreResult<Vec<usize>>;
+}
+
+/// Records of running workers, refreshed by their heartbeats.
+#[async_trait]
+pub trait WorkerRegistry: Send + Sync {
+    /// Creates or replaces the worker's record.
+    async fn heartbeat(&self, worker: &WorkerInfo) -> StoreResult<()>;
+
+    /// Every recorded worker, including ones whose heartbeat has expired.
+    async fn list_workers(&self) -> StoreResult<Vec<WorkerInfo>>;
+
+    /// Deletes the worker's record. Returns it only to the caller whose
+    /// delete took effect.
+    async fn remove_worker(&self, id: &str) -> StoreResult<Option<WorkerInfo>>;
+}
+
+/// What [`ResultCache::claim`] found for a fingerprint.
+#[derive(Debug)]
+pub enum Claim {
+    /// An identical job already finished with this result.
+    Hit(Box<Job>),
+    /// An identical job is running; the claiming job was added to its waiters.
+    Waiting,
+    /// Nothing cached or running; the claiming job should run.
+    Leader,
+}
+
+/// Results of finished jobs by [`Job::content_hash`], and the jobs waiting on
+/// an identical job that is still running.
+#[async_trait]
+pub trait ResultCache: Send + Sync {
+    /// Looks up `fingerprint`. When nothing is cached, either makes the job
+    /// stored under `key` the one that runs, or, if another job already
+    /// does, adds `key` to its waiters.
+    async fn claim(&self, fingerprint: &str, key: &str) -> StoreResult<Claim>;
+
+    /// Ends the run for `fingerprint`, caching `result` for `ttl` if given.
+    /// Returns the keys of the jobs that were waiting on it.
+    async fn settle(
+        &self,
+        fingerprint: &str,
+        result: Option<&Job>,
+        ttl: Duration,
+    ) -> StoreResult<Vec<String>>;
+
+    /// Drops the cached result for `fingerprint`.
+    async fn forget(&self, fingerprint: &str) -> StoreResult<()>;
+
+    /// Takes the keys of jobs waiting on runs whose claim expired without
+    /// settling, so they can be queued to run on their own.
+    async fn take_orphans(&self) -> StoreResult<Vec<String>>;
+}
+
+/// A backend that provides job storage, the job queue, the worker registry
+/// and the result cache.
+pub trait Backend: JobStore + JobQueue + WorkerRegistry + ResultCache {}
+
+impl<T: JobStore + JobQueue + WorkerRegistry + ResultCache> Backend for T {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{language_queue, JobStatus, Language, RedisConfig};
+    use super::{memory::MemoryClient, redis::RedisClient};
+
+    const WORKER: &str = "worker-1";
+
+    /// Redis to run the backend tests against as well, such as
+    /// `redis://127.0.0.1:6379`. Each run uses keys under a fresh hash tag.
+    const REDIS_URL: &str = "FLASH_TEST_REDIS_URL";
+
+    fn choices(queues: &[&str]) -> Vec<QueueChoice> {
+        queues
+            .iter()
+            .map(|name| QueueChoice {
+                name: name.to_string(),
+                turn: 0,
+            })
+            .collect()
+    }
+
+    async fn create(backend: &dyn Backend, queue: &str, language: &str) -> String {
+        let job = Job::new(String::new(), Language::from_name(language).unwrap());
+        let key = job.id.to_string();
+        backend.create_job(&key, queue, &job).await.unwrap();
+        key
+    }
+
+    async fn pop(backend: &dyn Backend, queues: &[&str]) -> Option<String> {
+        let job = backend.get_job_from_queue(WORKER, &choices(queues), None).await.unwrap();
+        job.map(|job| job.id.to_string())
+    }
+
+    async fn pops_oldest_of_first_queue(backend: &dyn Backend) {
+        let normal = create(backend, "jobs", "python").await;
+        let high = create(backend, "jobs:high", "python").await;
+        let later = create(backend, "jobs", "python").await;
+        assert_eq!(backend.queue_lens(&["jobs:high".to_string(), "jobs".to_string()]).await.unwrap(), [1, 2]);
+
+        for expected in [&high, &normal, &later] {
+            assert_eq!(pop(backend, &["jobs:high", "jobs"]).await.as_ref(), Some(expected));
+        }
+        let mut held = backend.processing_jobs(WORKER).await.unwrap();
+        held.sort();
+        let mut popped = vec![high, normal, later];
+        popped.sort();
+        assert_eq!(held, popped);
+
+        for key in &popped {
+            backend.ack_job(WORKER, key).await.unwrap();
+        }
+        assert!(backend.processing_jobs(WORKER).await.unwrap().is_empty());
+    }
+
+    async fn promotes_starving_entries(backend: &dyn Backend) {
+        let mut old = Job::new(String::new(), Language::from_name("python").unwrap());
+        old.timings.queued_at = Some(unix_millis() - 60_000);
+        let old_key = old.id.to_string();
+        backend.create_job(&old_key, "jobs:low", &old).await.unwrap();
+        let fresh = create(backend, "jobs:high", "python").await;
+
+        let queues = choices(&["jobs:high", "jobs:low"]);
+        let wait = Some(Duration::from_secs(30));
+        for expected in [&old_key, &fresh] {
+            let job = backend.get_job_from_queue(WORKER, &queues, wait).await.unwrap().unwrap();
+            assert_eq!(&job.id.to_string(), expected);
+            backend.ack_job(WORKER, expected).await.unwrap();
+        }
+    }
+
+    async fn requeues_to_the_front(backend: &dyn Backend) {
+        let first = create(backend, "requeue", "python").await;
+        let second = create(backend, "requeue", "python").await;
+        assert_eq!(pop(backend, &["requeue"]).await, Some(first.clone()));
+
+        assert_eq!(backend.requeue_processing(WORKER, None).await.unwrap(), 1);
+        assert_eq!(backend.requeue_processing(WORKER, None).await.unwrap(), 0);
+        assert!(backend.processing_jobs(WORKER).await.unwrap().is_empty());
+        for expected in [first, second] {
+            assert_eq!(pop(backend, &["requeue"]).await.as_ref(), Some(&expected));
+            backend.ack_job(WORKER, &expected).await.unwrap();
+        }
+    }
+
+    async fn skips_finished_jobs(backend: &dyn Backend) {
+        let key = create(backend, "finished", "python").await;
+        let mut job = backend.get_job(&key).await.unwrap().unwrap();
+        job.status = JobStatus::Accepted;
+        backend.store_job(&key, &job, None).await.unwrap();
+
+        assert_eq!(pop(backend, &["finished"]).await, None);
+        assert!(backend.processing_jobs(WORKER).await.unwrap().is_empty());
+    }
+
+    async fn routes_legacy_entries_by_language(backend: &dyn Backend) {
+        let python_queue = language_queue("legacy", "python");
+        let waiting = create(backend, &python_queue, "python").await;
+        let first = create(backend, "legacy", "python").await;
+        create(backend, "legacy", "cpp").await;
+        let second = create(backend, "legacy", "python").await;
+
+        assert_eq!(backend.route_by_language("legacy", &["python"]).await.unwrap(), 2);
+        let lens = backend.queue_lens(&["legacy".to_string(), python_queue.clone()]).await.unwrap();
+        assert_eq!(lens, [1, 3]);
+        for expected in [first, second, waiting] {
+            assert_eq!(pop(backend, &[&python_queue]).await.as_ref(), Some(&expected));
+            backend.ack_job(WORKER, &expected).await.unwrap();
+        }
+    }
+
+    async fn check_queue(backend: &dyn Backend) {
+        pops_oldest_of_first_queue(backend).await;
+        promotes_starving_entries(backend).await;
+        requeues_to_the_front(backend).await;
+        skips_finished_jobs(backend).await;
+        routes_legacy_entries_by_language(backend).await;
+    }
+
+    #[tokio::test]
+    async fn memory_backend_queue() {
+        check_queue(&MemoryClient::new()).await;
+    }
+
+    #[tokio::test]
+    async fn redis_backend_queue() {
+        let Ok(url) = std::env::var(REDIS_URL) else {
+            eprintln!("{} is not set, skipping", REDIS_URL);
+            return;
+        };
+        let config = RedisConfig {
+            hash_tag: format!("flash-test-{}", uuid::Uuid::new_v4()),
+            ..Default::default()
+        };
+        let redis = RedisClient::new(&url, config).unwrap();
+        redis.test_connection().await.unwrap();
+        check_queue(&redis).await;
+    }
+}
@@ -1,37 +1,82 @@
-use bincode;
-use deadpool_redis::{redis, Config, Connection, Pool, Runtime};
-use futures::TryFutureExt;
-use redis::{AsyncCommands, RedisError, RedisResult};
-use serde::{de::DeserializeOwned, Serialize};
-use std::time::Duration;
+use super::{
+    breaker::CircuitBreaker,
+    codec::{decode_entry, decode_job, encode_entry, encode_job},
+    Claim, JobQueue, JobStore, QueueChoice, QueueEntry, ResultCache, StoreError, StoreResult, WorkerRegistry,
+    CLAIM_TTL, FINISHED_INDEX, WORKERS_KEY,
+};
+use crate::core::{
+    language_queue, metrics::metrics, unix_millis, Job, RedisConfig, RedisTopology, WorkerInfo,
+};
+use async_trait::async_trait;
+use deadpool_redis::{
+    redis, Config, Connection, ConnectionInfo, Pool, PoolConfig, Runtime, Timeouts,
+};
+use redis::{AsyncCommands, ErrorKind, IntoConnectionInfo, RedisError, RedisResult, Value};
+use std::{
+    collections::hash_map::RandomState,
+    future::Future,
+    hash::BuildHasher,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 use tracing;
 
+/// Longest backoff between retries, before jitter.
+const MAX_BACKOFF: Duration = Duration::from_secs(2);
+/// Hash slots in a Redis Cluster.
+const CLUSTER_SLOTS: u16 = 16384;
+/// Set of fingerprints with jobs waiting on their run.
+const WAITING_INDEX: &str = "cache:pending";
+
 #[derive(Clone)]
 pub struct RedisClient {
-    pool: Pool,
+    redis_url: String,
+    options: Arc<RedisConfig>,
+    /// Pool to the primary. Under Sentinel or Cluster it is dropped when the
+    /// primary may have moved, and found again on the next command.
+    pool: Arc<RwLock<Option<Pool>>>,
+    resolving: Arc<tokio::sync::Mutex<()>>,
+    breaker: Arc<CircuitBreaker>,
+}
+
+/// Whether a command may be run again after a transient failure.
+#[derive(Clone, Copy, PartialEq)]
+enum Retry {
+    /// Running it twice does no harm.
+    Always,
+    /// Only when it certainly didn't run: no connection was had, or the
+    /// server turned it away. Used for pushes, which would duplicate entries.
+    IfNotRun,
+}
+
+impl From<RedisError> for StoreError {
+    fn from(e: RedisError) -> Self {
+        // Every failed command and connection passes through here
+        metrics().redis_errors.inc();
+        StoreError::Backend(e.to_string())
+    }
 }
 
 impl RedisClient {
-    pub fn new(redis_url: &str) -> RedisResult<Self> {
-        let cfg = Config::from_url(redis_url);
-        let pool = cfg
-            .create_pool(Some(Runtime::Tokio1))
-            .map_err(|e| {
-                tracing::error!("Failed to create Redis pool: {:?}", e);
-                RedisError::from((redis::ErrorKind::IoError, "Pool creation error", format!("Error: {}", e)))
-            })?;
-        
-        // Test connection to confirm Redis is available
-        let client = Self { pool };
-        let _ = client.test_connection().map_err(|e| {
-            tracing::error!("Redis connection test failed: {:?}", e);
-            e
-        });
-        
+    pub fn new(redis_url: &str, options: RedisConfig) -> RedisResult<Self> {
+        let client = Self {
+            redis_url: redis_url.to_string(),
+            breaker: Arc::new(CircuitBreaker::new(
+                options.breaker_threshold,
+                Duration::from_secs(options.breaker_cooldown),
+            )),
+            options: Arc::new(options),
+            pool: Arc::new(RwLock::new(None)),
+            resolving: Arc::new(tokio::sync::Mutex::new(())),
+        };
+        if client.options.topology == RedisTopology::Standalone {
+            let pool = client.create_pool(redis_url.into_connection_info()?)?;
+            *client.pool.write().unwrap() = Some(pool);
+        }
         Ok(client)
-}
-    
-    async fn test_connection(&self) -> RedisResult<()> {
+    }
+
+    pub async fn test_connection(&self) -> RedisResult<()> {
         let mut conn = self.get_conn().await?;
         redis::cmd("PING").query_async(&mut conn).await.map_err(|e| {
             tracing::error!("Redis PING failed: {:?}", e);
@@ -41,7 +86,8 @@ impl RedisClient {
 
     #[tracing::instrument(skip(self), level = "debug")]
     async fn get_conn(&self) -> RedisResult<Connection> {
-        self.pool
+        self.pool()
+            .await?
             .get()
             .await
             .map_err(|e| {
@@ -50,97 +96,872 @@ impl RedisClient {
             })
     }
 
-    #[tracing::instrument(skip(self, value), level = "debug")]
-    pub async fn store_job<T: Serialize>(
-        &self,
-        key: &str,
-        value: &T,
-        ttl: Option<Duration>,
-    ) -> RedisResult<()> {
-        let mut conn = self.get_conn().await?;
-        let serialized = bincode::serialize(value).map_err(|e| {
-            tracing::error!("Failed to serialize job: {:?}", e);
-            RedisError::from((redis::ErrorKind::TypeError, "Serialization failed", format!("Error: {}", e)))
-        })?;
+    /// `key` under the configured hash tag.
+    fn key(&self, key: &str) -> String {
+        match self.options.hash_tag.as_str() {
+            "" => key.to_string(),
+            tag => format!("{{{}}}:{}", tag, key),
+        }
+    }
+
+    fn command_timeout(&self) -> Duration {
+        Duration::from_millis(self.options.command_timeout_ms)
+    }
+
+    fn connect_timeout(&self) -> Duration {
+        Duration::from_millis(self.options.connect_timeout_ms)
+    }
+
+    /// Runs `command` on a pooled connection within `timeout`, retrying
+    /// transient failures with jittered exponential backoff. Fails fast while
+    /// the circuit breaker is open.
+    async fn run<T, F, Fut>(&self, retry: Retry, timeout: Duration, command: F) -> StoreResult<T>
+    where
+        F: Fn(Connection) -> Fut,
+        Fut: Future<Output = RedisResult<T>>,
+    {
+        if !self.breaker.allow() {
+            return Err(StoreError::Backend("Redis unavailable, circuit breaker open".to_string()));
+        }
+
+        let mut attempt = 0;
+        loop {
+            // A connection that timed out goes back to the pool, which
+            // discards it when its next PING gets the stale reply
+            let (error, ran) = match self.get_conn().await {
+                Ok(conn) => match tokio::time::timeout(timeout, command(conn)).await {
+                    Ok(Ok(value)) => {
+                        self.breaker.record_success();
+                        return Ok(value);
+                    }
+                    Ok(Err(e)) => (e, true),
+                    Err(_) => (timed_out(timeout), true),
+                },
+                Err(e) => (e, false),
+            };
 
-        if let Some(ttl) = ttl {
-            conn.set_ex(key, serialized, ttl.as_secs() as usize).await
-        } else {
-            conn.set(key, serialized).await
+            if !is_transient(&error) {
+                // Redis answered, so it's up
+                self.breaker.record_success();
+                return Err(error.into());
+            }
+            if may_have_moved(&error) {
+                self.invalidate();
+            }
+            let retryable = retry == Retry::Always || !ran || is_rejection(&error);
+            if !retryable || attempt >= self.options.retries {
+                self.breaker.record_failure();
+                return Err(error.into());
+            }
+            attempt += 1;
+            metrics().redis_retries.inc();
+            tracing::warn!("Redis command failed, retry {} of {}: {}", attempt, self.options.retries, error);
+            tokio::time::sleep(self.backoff(attempt)).await;
+        }
+    }
+
+    /// Exponential backoff from `retry_backoff_ms`, with full jitter so
+    /// clients that failed together don't retry together.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let ceiling = Duration::from_millis(self.options.retry_backoff_ms)
+            .saturating_mul(1 << attempt.min(16))
+            .min(MAX_BACKOFF);
+        // Each RandomState is freshly keyed, which is random enough here
+        let random = RandomState::new().hash_one(attempt);
+        ceiling.mul_f64((random % 1000) as f64 / 1000.0)
+    }
+
+    /// The pool to the current primary, found first if there's none.
+    async fn pool(&self) -> RedisResult<Pool> {
+        let current = self.pool.read().unwrap().clone();
+        if let Some(pool) = current {
+            return Ok(pool);
+        }
+
+        // One task looks the primary up while the others wait for it
+        let _resolving = self.resolving.lock().await;
+        let current = self.pool.read().unwrap().clone();
+        if let Some(pool) = current {
+            return Ok(pool);
         }
+        let info = match self.options.topology {
+            RedisTopology::Standalone => self.redis_url.as_str().into_connection_info()?,
+            RedisTopology::Sentinel => self.sentinel_master().await?,
+            RedisTopology::Cluster => self.cluster_owner().await?,
+        };
+        let pool = self.create_pool(info)?;
+        *self.pool.write().unwrap() = Some(pool.clone());
+        Ok(pool)
+    }
+
+    /// Drops the pool so the primary is looked up again. A standalone
+    /// server doesn't move, and its pool replaces broken connections itself.
+    fn invalidate(&self) {
+        if self.options.topology != RedisTopology::Standalone {
+            self.pool.write().unwrap().take();
+        }
+    }
+
+    fn create_pool(&self, info: redis::ConnectionInfo) -> RedisResult<Pool> {
+        let timeout = Some(self.connect_timeout());
+        let mut pool = PoolConfig::default();
+        if self.options.pool_size > 0 {
+            pool.max_size = self.options.pool_size;
+        }
+        pool.timeouts = Timeouts {
+            wait: timeout,
+            create: timeout,
+            recycle: timeout,
+        };
+        let cfg = Config {
+            url: None,
+            connection: Some(ConnectionInfo::from(info)),
+            pool: Some(pool),
+        };
+        cfg.create_pool(Some(Runtime::Tokio1)).map_err(|e| {
+            tracing::error!("Failed to create Redis pool: {:?}", e);
+            RedisError::from((redis::ErrorKind::IoError, "Pool creation error", format!("Error: {}", e)))
+        })
+    }
+
+    /// A standalone connection to `url`, for topology lookups.
+    async fn connect(&self, url: &str) -> RedisResult<redis::aio::Connection> {
+        let client = redis::Client::open(url)?;
+        let timeout = self.connect_timeout();
+        tokio::time::timeout(timeout, client.get_async_connection())
+            .await
+            .unwrap_or_else(|_| Err(timed_out(timeout)))
+    }
+
+    /// `redis_url` pointed at `host:port`, keeping its credentials, database
+    /// and TLS setting.
+    fn primary_at(&self, host: String, port: u16) -> RedisResult<redis::ConnectionInfo> {
+        let mut info = self.redis_url.as_str().into_connection_info()?;
+        info.addr = match info.addr {
+            redis::ConnectionAddr::TcpTls { insecure, .. } => redis::ConnectionAddr::TcpTls { host, port, insecure },
+            _ => redis::ConnectionAddr::Tcp(host, port),
+        };
+        Ok(info)
+    }
+
+    /// Asks the sentinels in turn where the master is.
+    async fn sentinel_master(&self) -> RedisResult<redis::ConnectionInfo> {
+        let name = &self.options.master_name;
+        let mut last_error = None;
+        for node in &self.options.nodes {
+            let lookup = async {
+                let mut conn = self.connect(node).await?;
+                redis::cmd("SENTINEL")
+                    .arg("get-master-addr-by-name")
+                    .arg(name)
+                    .query_async::<_, Option<(String, u16)>>(&mut conn)
+                    .await
+            };
+            match lookup.await {
+                Ok(Some((host, port))) => {
+                    tracing::info!("Sentinels report master {} at {}:{}", name, host, port);
+                    return self.primary_at(host, port);
+                }
+                Ok(None) => last_error = Some(lookup_failed(format!("a sentinel doesn't know master {:?}", name))),
+                Err(e) => {
+                    tracing::warn!("Sentinel lookup failed: {}", e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| lookup_failed("no sentinels configured".to_string())))
+    }
+
+    /// Asks the cluster nodes in turn which primary owns the hash tag's
+    /// slot, where every key lives.
+    async fn cluster_owner(&self) -> RedisResult<redis::ConnectionInfo> {
+        let slot = key_slot(&self.options.hash_tag);
+        let seeds = match self.options.nodes.is_empty() {
+            true => std::slice::from_ref(&self.redis_url),
+            false => self.options.nodes.as_slice(),
+        };
+        let mut last_error = None;
+        for node in seeds {
+            let lookup = async {
+                let mut conn = self.connect(node).await?;
+                redis::cmd("CLUSTER").arg("SLOTS").query_async::<_, Value>(&mut conn).await
+            };
+            match lookup.await {
+                Ok(slots) => match slot_owner(&slots, slot) {
+                    Some((host, port)) => {
+                        tracing::info!("Cluster slot {} is served by {}:{}", slot, host, port);
+                        return self.primary_at(host, port);
+                    }
+                    None => last_error = Some(lookup_failed(format!("no cluster node serves slot {}", slot))),
+                },
+                Err(e) => {
+                    tracing::warn!("Cluster lookup failed: {}", e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| lookup_failed("no cluster nodes configured".to_string())))
+    }
+}
+
+/// Failures worth retrying, that a later attempt or another node may not hit.
+fn is_transient(e: &RedisError) -> bool {
+    // The kind also covers failures to get a connection, which aren't `io::Error`s
+    e.kind() == ErrorKind::IoError || e.is_timeout() || e.is_connection_dropped() || is_rejection(e)
+}
+
+/// Errors the server answers with instead of running the command.
+fn is_rejection(e: &RedisError) -> bool {
+    e.is_cluster_error()
+        || matches!(
+            e.kind(),
+            ErrorKind::BusyLoadingError | ErrorKind::MasterDown | ErrorKind::ReadOnly
+        )
+}
+
+/// Errors after which the primary may be somewhere else: it went away, was
+/// demoted, or the slot moved.
+fn may_have_moved(e: &RedisError) -> bool {
+    e.kind() == ErrorKind::IoError
+        || e.is_connection_dropped()
+        || matches!(
+            e.kind(),
+            ErrorKind::Moved | ErrorKind::ClusterDown | ErrorKind::MasterDown | ErrorKind::ReadOnly
+        )
+}
+
+fn timed_out(timeout: Duration) -> RedisError {
+    RedisError::from((ErrorKind::IoError, "Redis command timed out", format!("no reply within {:?}", timeout)))
+}
+
+fn lookup_failed(detail: String) -> RedisError {
+    RedisError::from((ErrorKind::IoError, "Failed to find the Redis primary", detail))
+}
+
+/// The cluster slot of `key`, the CRC16 (XMODEM) of it modulo the slot count.
+fn key_slot(key: &str) -> u16 {
+    let crc = key.bytes().fold(0u16, |crc, byte| {
+        (0..8).fold(crc ^ ((byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    });
+    crc % CLUSTER_SLOTS
+}
+
+/// The primary serving `slot` in a `CLUSTER SLOTS` reply.
+fn slot_owner(slots: &Value, slot: u16) -> Option<(String, u16)> {
+    let Value::Bulk(ranges) = slots else {
+        return None;
+    };
+    ranges.iter().find_map(|range| {
+        let Value::Bulk(range) = range else {
+            return None;
+        };
+        let (Some(Value::Int(start)), Some(Value::Int(end)), Some(Value::Bulk(primary))) =
+            (range.first(), range.get(1), range.get(2))
+        else {
+            return None;
+        };
+        if !(*start..=*end).contains(&(slot as i64)) {
+            return None;
+        }
+        let (Some(Value::Data(host)), Some(Value::Int(port))) = (primary.first(), primary.get(1)) else {
+            return None;
+        };
+        Some((String::from_utf8_lossy(host).into_owned(), *port as u16))
+    })
+}
+
+#[async_trait]
+impl JobStore for RedisClient {
+    #[tracing::instrument(skip(self, job), level = "debug")]
+    async fn store_job(&self, key: &str, job: &Job, ttl: Option<Duration>) -> StoreResult<()> {
+        let serialized = encode_job(job)?;
+        let (job_key, index) = (self.key(key), self.key(FINISHED_INDEX));
+
+        self.run(Retry::Always, self.command_timeout(), |mut conn| {
+            let mut pipe = redis::pipe();
+            pipe.atomic();
+            if let Some(ttl) = ttl {
+                pipe.set_ex(&job_key, &serialized, ttl.as_secs() as usize).ignore();
+            } else {
+                pipe.set(&job_key, &serialized).ignore();
+            }
+            if let Some(finished_at) = job.finished_at {
+                pipe.zadd(&index, key, finished_at).ignore();
+            }
+            async move { pipe.query_async::<_, ()>(&mut conn).await }
+        })
+        .await
     }
 
     #[tracing::instrument(skip(self), level = "debug")]
-    pub async fn get_job<T: DeserializeOwned>(&self, key: &str) -> RedisResult<Option<T>> {
-        let mut conn = self.get_conn().await?;
-        let data: Option<Vec<u8>> = conn.get(key).await?;
+    async fn get_job(&self, key: &str) -> StoreResult<Option<Job>> {
+        let key = &self.key(key);
+        let data: Option<Vec<u8>> = self
+            .run(Retry::Always, self.command_timeout(), |mut conn| async move { conn.get(key).await })
+            .await?;
 
-        data.map(|d| bincode::deserialize(&d))
-            .transpose()
-            .map_err(|e| {
-                tracing::error!("Failed to deserialize job: {:?}", e);
-                redis::RedisError::from((
-                    redis::ErrorKind::TypeError,
-                    "Deserialization failed",
-                    e.to_string(),
-                ))
-            })
+        data.map(|d| decode_job(&d)).transpose()
+    }
+
+    async fn ping(&self) -> StoreResult<()> {
+        self.run(Retry::Always, self.command_timeout(), |mut conn| async move {
+            redis::cmd("PING").query_async(&mut conn).await
+        })
+        .await
     }
 
     #[tracing::instrument(skip(self), level = "debug")]
-    pub async fn get_job_from_queue<T: DeserializeOwned>(
+    async fn sweep_expired(&self, before: i64) -> StoreResult<usize> {
+        let index = &self.key(FINISHED_INDEX);
+        self.run(Retry::Always, self.command_timeout(), |mut conn| async move {
+            conn.zrembyscore(index, "-inf", format!("({}", before)).await
+        })
+        .await
+    }
+
+    #[tracing::instrument(skip(self), level = "debug")]
+    async fn finished_since(&self, since: i64) -> StoreResult<Vec<(String, i64)>> {
+        let index = &self.key(FINISHED_INDEX);
+        self.run(Retry::Always, self.command_timeout(), |mut conn| async move {
+            conn.zrangebyscore_withscores(index, since, "+inf").await
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl JobQueue for RedisClient {
+    #[tracing::instrument(skip(self, job), level = "debug")]
+    async fn create_job(&self, key: &str, queue: &str, job: &Job) -> StoreResult<()> {
+        let serialized = &encode_job(job)?;
+        let entry = &encode_entry(&QueueEntry::new(key, job))?;
+        let (key, queue) = (&self.key(key), &self.key(queue));
+
+        // Store the job in Redis and enqueue its key. Workers pop from the
+        // right, so pushing on the left keeps the queue first in, first out.
+        self.run(Retry::IfNotRun, self.command_timeout(), |mut conn| async move {
+            redis::pipe()
+                .atomic()
+                .set(key, serialized)
+                .ignore()
+                .lpush(queue, entry)
+                .ignore()
+                .query_async::<_, ()>(&mut conn)
+                .await
+        })
+        .await
+    }
+
+    #[tracing::instrument(skip(self), level = "debug")]
+    async fn get_job_from_queue(
         &self,
-        queue: &str,
-    ) -> RedisResult<Option<T>> {
-        let mut conn = self.get_conn().await?;
+        worker: &str,
+        queues: &[QueueChoice],
+        max_wait: Option<Duration>,
+    ) -> StoreResult<Option<Job>> {
+        let promote_before = match max_wait {
+            Some(max_wait) => unix_millis() - max_wait.as_millis() as i64,
+            None => i64::MIN,
+        };
+        let mut keys = vec![self.processing_key(worker)];
+        keys.extend(queues.iter().map(|queue| self.key(&queue.name)));
+        let keys = &keys;
+        let turns = &queues.iter().map(|queue| queue.turn).collect::<Vec<_>>();
+        // The pop doesn't block: a reply that came after the timeout would be
+        // thrown away with its connection. Popping again after a failure is
+        // safe, since an entry popped by the lost attempt waits in this
+        // worker's processing set.
+        let result: Option<(String, Vec<u8>)> = self
+            .run(Retry::Always, self.command_timeout(), |mut conn| async move {
+                redis::cmd("EVAL")
+                    .arg(POP_ENTRY_SCRIPT)
+                    .arg(keys.len())
+                    .arg(keys)
+                    .arg(promote_before)
+                    .arg(turns)
+                    .query_async(&mut conn)
+                    .await
+            })
+            .await?;
+
+        let Some((_list_name, data)) = result else {
+            return Ok(None);
+        };
 
-        // Use BRPOP with 1-second timeout to block until job arrives
-        let result: Option<(String, Vec<u8>)> = conn.brpop(queue, 1).await?;
-
-        match result {
-            Some((_list_name, data)) => {
-                // Deserialize the binary data
-                let job = bincode::deserialize(&data).map_err(|e| {
-                    tracing::error!("Failed to deserialize queue job: {:?}", e);
-                    redis::RedisError::from((
-                        redis::ErrorKind::TypeError,
-                        "Deserialization failed",
-                        e.to_string(),
-                    ))
-                })?;
-                Ok(Some(job))
+        let Some(entry) = decode_entry(&data) else {
+            // Full job queued before the queue carried only keys
+            return Ok(Some(decode_job(&data)?));
+        };
+
+        let key = &self.key(&entry.key);
+        let job: Option<Vec<u8>> = self
+            .run(Retry::Always, self.command_timeout(), |mut conn| async move { conn.get(key).await })
+            .await?;
+        let job = job.map(|job| decode_job(&job)).transpose()?;
+        match job {
+            Some(job) if !job.is_finished() => Ok(Some(job)),
+            Some(_) => {
+                // Finished by a worker that died before acking it
+                self.ack_job(worker, &entry.key).await?;
+                Ok(None)
             }
             None => {
+                tracing::warn!("Dropping queue entry for missing job {}", entry.key);
+                self.ack_job(worker, &entry.key).await?;
                 Ok(None)
             }
         }
     }
 
-    #[tracing::instrument(skip(self, value), level = "debug")]
-    pub async fn create_job<T: Serialize>(
+    #[tracing::instrument(skip(self), level = "debug")]
+    async fn ack_job(&self, worker: &str, key: &str) -> StoreResult<()> {
+        let processing = &self.processing_key(worker);
+        self.run(Retry::Always, self.command_timeout(), |mut conn| async move {
+            conn.hdel::<_, _, ()>(processing, key).await
+        })
+        .await
+    }
+
+    #[tracing::instrument(skip(self), level = "debug")]
+    async fn processing_jobs(&self, worker: &str) -> StoreResult<Vec<String>> {
+        let processing = &self.processing_key(worker);
+        self.run(Retry::Always, self.command_timeout(), |mut conn| async move {
+            conn.hkeys(processing).await
+        })
+        .await
+    }
+
+    #[tracing::instrument(skip(self), level = "debug")]
+    async fn requeue_processing(&self, worker: &str, keys: Option<&[String]>) -> StoreResult<usize> {
+        let processing = &self.processing_key(worker);
+        let keys = keys.unwrap_or_default();
+        // Running it again finds the entries already moved
+        self.run(Retry::Always, self.command_timeout(), |mut conn| async move {
+            redis::cmd("EVAL")
+                .arg(REQUEUE_PROCESSING_SCRIPT)
+                .arg(1)
+                .arg(processing)
+                .arg(keys)
+                .query_async(&mut conn)
+                .await
+        })
+        .await
+    }
+
+    #[tracing::instrument(skip(self), level = "debug")]
+    async fn route_by_language(&self, queue: &str, languages: &[&str]) -> StoreResult<usize> {
+        let mut keys = vec![self.key(queue)];
+        keys.extend(languages.iter().map(|language| self.key(&language_queue(queue, language))));
+        let keys = &keys;
+        // Running it again finds the entries already moved
+        let moved = self
+            .run(Retry::Always, self.command_timeout(), |mut conn| async move {
+                redis::cmd("EVAL")
+                    .arg(ROUTE_BY_LANGUAGE_SCRIPT)
+                    .arg(keys.len())
+                    .arg(keys)
+                    .arg(languages)
+                    .query_async(&mut conn)
+                    .await
+            })
+            .await?;
+        if moved > 0 {
+            tracing::info!("Moved {} queued jobs from {} to their languages' queues", moved, queue);
+        }
+        Ok(moved)
+    }
+
+    #[tracing::instrument(skip(self), level = "debug")]
+    async fn queue_lens(&self, queues: &[String]) -> StoreResult<Vec<usize>> {
+        let queues = &queues.iter().map(|queue| self.key(queue)).collect::<Vec<_>>();
+        self.run(Retry::Always, self.command_timeout(), |mut conn| async move {
+            let mut pipe = redis::pipe();
+            for queue in queues {
+                pipe.llen(queue);
+            }
+            pipe.query_async(&mut conn).await
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl WorkerRegistry for RedisClient {
+    #[tracing::instrument(skip(self, worker), level = "debug")]
+    async fn heartbeat(&self, worker: &WorkerInfo) -> StoreResult<()> {
+        let serialized =
+            &serde_json::to_vec(worker).map_err(|e| StoreError::Serialization(e.to_string()))?;
+        let workers = &self.key(WORKERS_KEY);
+        self.run(Retry::Always, self.command_timeout(), |mut conn| async move {
+            conn.hset::<_, _, _, ()>(workers, &worker.id, serialized).await
+        })
+        .await
+    }
+
+    #[tracing::instrument(skip(self), level = "debug")]
+    async fn list_workers(&self) -> StoreResult<Vec<WorkerInfo>> {
+        let key = &self.key(WORKERS_KEY);
+        let workers: Vec<Vec<u8>> = self
+            .run(Retry::Always, self.command_timeout(), |mut conn| async move { conn.hvals(key).await })
+            .await?;
+        Ok(workers
+            .iter()
+            .filter_map(|data| match serde_json::from_slice(data) {
+                Ok(worker) => Some(worker),
+                Err(e) => {
+                    tracing::warn!("Skipping undecodable worker record: {}", e);
+                    None
+                }
+            })
+            .collect())
+    }
+
+    #[tracing::instrument(skip(self), level = "debug")]
+    async fn remove_worker(&self, id: &str) -> StoreResult<Option<WorkerInfo>> {
+        let key = &self.key(WORKERS_KEY);
+        // Removing again would return nothing to the caller that removed it
+        let data: Option<Vec<u8>> = self
+            .run(Retry::IfNotRun, self.command_timeout(), |mut conn| async move {
+                redis::cmd("EVAL")
+                    .arg(REMOVE_WORKER_SCRIPT)
+                    .arg(1)
+                    .arg(key)
+                    .arg(id)
+                    .query_async(&mut conn)
+                    .await
+            })
+            .await?;
+        data.map(|data| serde_json::from_slice(&data))
+            .transpose()
+            .map_err(|e| StoreError::Serialization(e.to_string()))
+    }
+}
+
+#[async_trait]
+impl ResultCache for RedisClient {
+    #[tracing::instrument(skip(self), level = "debug")]
+    async fn claim(&self, fingerprint: &str, key: &str) -> StoreResult<Claim> {
+        let keys = &self.cache_keys(fingerprint);
+        // Claiming twice would leave the job waiting on itself
+        let (outcome, data): (String, Option<Vec<u8>>) = self
+            .run(Retry::IfNotRun, self.command_timeout(), |mut conn| async move {
+                redis::cmd("EVAL")
+                    .arg(CLAIM_SCRIPT)
+                    .arg(keys.len())
+                    .arg(keys)
+                    .arg(key)
+                    .arg(CLAIM_TTL.as_secs())
+                    .arg(fingerprint)
+                    .query_async(&mut conn)
+                    .await
+            })
+            .await?;
+        match (outcome.as_str(), data) {
+            ("hit", Some(data)) => Ok(Claim::Hit(Box::new(decode_job(&data)?))),
+            ("waiting", _) => Ok(Claim::Waiting),
+            _ => Ok(Claim::Leader),
+        }
+    }
+
+    #[tracing::instrument(skip(self, result), level = "debug")]
+    async fn settle(
         &self,
-        key: &str,
-        queue: &str,
-        value: &T,
-    ) -> RedisResult<()> {
-        let mut conn = self.get_conn().await?;
-        let serialized = bincode::serialize(value).map_err(|e| {
-            tracing::error!("Failed to serialize job for queue: {:?}", e);
-            redis::RedisError::from((
-                redis::ErrorKind::TypeError,
-                "Serialization failed",
-                e.to_string(),
-            ))
-        })?;
-
-        // Store the job in Redis and enqueue it
-        redis::pipe()
-            .atomic()
-            .set(key, &serialized)
-            .ignore()
-            .rpush(queue, &serialized)
-            .ignore()
-            .query_async(&mut conn)
-            .await
+        fingerprint: &str,
+        result: Option<&Job>,
+        ttl: Duration,
+    ) -> StoreResult<Vec<String>> {
+        let keys = &self.cache_keys(fingerprint);
+        let result = &result.map(encode_job).transpose()?;
+        // Settling twice would drop the waiters the first attempt read
+        self.run(Retry::IfNotRun, self.command_timeout(), |mut conn| async move {
+            let mut cmd = redis::cmd("EVAL");
+            cmd.arg(SETTLE_SCRIPT)
+                .arg(keys.len())
+                .arg(keys)
+                .arg(ttl.as_secs().max(1))
+                .arg(fingerprint);
+            if let Some(result) = result {
+                cmd.arg(result);
+            }
+            cmd.query_async(&mut conn).await
+        })
+        .await
+    }
+
+    #[tracing::instrument(skip(self), level = "debug")]
+    async fn forget(&self, fingerprint: &str) -> StoreResult<()> {
+        let key = &self.cache_keys(fingerprint)[0];
+        self.run(Retry::Always, self.command_timeout(), |mut conn| async move {
+            conn.del::<_, ()>(key).await
+        })
+        .await
+    }
+
+    #[tracing::instrument(skip(self), level = "debug")]
+    async fn take_orphans(&self) -> StoreResult<Vec<String>> {
+        let (index, prefix) = (&self.key(WAITING_INDEX), &self.key("cache:"));
+        // Taking twice would drop the orphans the first attempt read
+        self.run(Retry::IfNotRun, self.command_timeout(), |mut conn| async move {
+            redis::cmd("EVAL")
+                .arg(TAKE_ORPHANS_SCRIPT)
+                .arg(1)
+                .arg(index)
+                .arg(prefix)
+                .query_async(&mut conn)
+                .await
+        })
+        .await
+    }
+}
+
+impl RedisClient {
+    /// Hash of the entries `worker` popped and hasn't acked, by job key.
+    fn processing_key(&self, worker: &str) -> String {
+        self.key(&format!("processing:{}", worker))
     }
-}
\ No newline at end of file
+
+    /// The cached result, running-job claim and waiter list of `fingerprint`,
+    /// and the index of fingerprints with waiters.
+    fn cache_keys(&self, fingerprint: &str) -> [String; 4] {
+        let result = format!("cache:{}", fingerprint);
+        [
+            self.key(&result),
+            self.key(&format!("{}:inflight", result)),
+            self.key(&format!("{}:waiting", result)),
+            self.key(WAITING_INDEX),
+        ]
+    }
+}
+
+/// Returns `{'hit', result}` if `KEYS[1]` holds a cached result. Otherwise
+/// claims the run for `ARGV[1]` by setting `KEYS[2]` and returns `{'leader'}`,
+/// or if it's already claimed, appends `ARGV[1]` to the waiters in `KEYS[3]`,
+/// records fingerprint `ARGV[3]` in the index `KEYS[4]` and returns
+/// `{'waiting'}`. Claims expire after `ARGV[2]` seconds; waiter lists stay
+/// until the run settles or its waiters are taken as orphans.
+const CLAIM_SCRIPT: &str = r#"
+local result = redis.call('GET', KEYS[1])
+if result then
+    return {'hit', result}
+end
+if redis.call('SET', KEYS[2], ARGV[1], 'NX', 'EX', ARGV[2]) then
+    return {'leader', false}
+end
+redis.call('RPUSH', KEYS[3], ARGV[1])
+redis.call('SADD', KEYS[4], ARGV[3])
+return {'waiting', false}
+"#;
+
+/// Caches `ARGV[3]`, if given, in `KEYS[1]` for `ARGV[1]` seconds, releases
+/// the claim in `KEYS[2]`, drops fingerprint `ARGV[2]` from the index
+/// `KEYS[4]` and returns and clears the waiters in `KEYS[3]`.
+const SETTLE_SCRIPT: &str = r#"
+if ARGV[3] then
+    redis.call('SET', KEYS[1], ARGV[3], 'EX', ARGV[1])
+end
+redis.call('DEL', KEYS[2])
+redis.call('SREM', KEYS[4], ARGV[2])
+local waiting = redis.call('LRANGE', KEYS[3], 0, -1)
+redis.call('DEL', KEYS[3])
+return waiting
+"#;
+
+/// Returns and clears the waiters of every fingerprint in the index
+/// `KEYS[1]` whose claim has expired. `ARGV[1]` is the prefix of the
+/// fingerprints' keys.
+const TAKE_ORPHANS_SCRIPT: &str = r#"
+local orphans = {}
+for _, fingerprint in ipairs(redis.call('SMEMBERS', KEYS[1])) do
+    local run = ARGV[1] .. fingerprint
+    if redis.call('EXISTS', run .. ':inflight') == 0 then
+        for _, key in ipairs(redis.call('LRANGE', run .. ':waiting', 0, -1)) do
+            table.insert(orphans, key)
+        end
+        redis.call('DEL', run .. ':waiting')
+        redis.call('SREM', KEYS[1], fingerprint)
+    end
+end
+return orphans
+"#;
+
+/// Deletes worker `ARGV[1]` from the hash `KEYS[1]` and returns its record,
+/// or nil if it was already gone.
+const REMOVE_WORKER_SCRIPT: &str = r#"
+local record = redis.call('HGET', KEYS[1], ARGV[1])
+if record then
+    redis.call('HDEL', KEYS[1], ARGV[1])
+end
+return record
+"#;
+
+/// Pops the oldest entry of the first non-empty queue in `KEYS[2..]`, unless
+/// the oldest entry of a later queue was queued before `ARGV[1]` (unix ms).
+/// Of those, the entry of the earliest tenant turn is taken, the longest
+/// waiting within it; `ARGV[i]` is the turn of `KEYS[i]`. Entries without a
+/// timestamp count as old. The entry is recorded with its queue under its
+/// job key in the processing hash `KEYS[1]`; full jobs queued before entries
+/// existed have no key and aren't. Returns the queue and the entry, or nil.
+const POP_ENTRY_SCRIPT: &str = r#"
+local function take(queue)
+    local entry = redis.call('RPOP', queue)
+    if entry then
+        local ok, decoded = pcall(cjson.decode, entry)
+        if ok and type(decoded) == 'table' and type(decoded.key) == 'string' and decoded.key ~= '' then
+            redis.call('HSET', KEYS[1], decoded.key, cjson.encode({queue = queue, entry = entry}))
+        end
+    end
+    return entry
+end
+local promote, promoted_turn, promoted_at
+for i = 3, #KEYS do
+    local entry = redis.call('LINDEX', KEYS[i], -1)
+    if entry then
+        local ok, decoded = pcall(cjson.decode, entry)
+        local queued_at = 0
+        if ok and type(decoded) == 'table' and type(decoded.queued_at) == 'number' then
+            queued_at = decoded.queued_at
+        end
+        local turn = tonumber(ARGV[i])
+        if queued_at < tonumber(ARGV[1]) and (promote == nil or turn < promoted_turn
+                or (turn == promoted_turn and queued_at < promoted_at)) then
+            promote, promoted_turn, promoted_at = KEYS[i], turn, queued_at
+        end
+    end
+end
+if promote then
+    return {promote, take(promote)}
+end
+for i = 2, #KEYS do
+    local entry = take(KEYS[i])
+    if entry then
+        return {KEYS[i], entry}
+    end
+end
+return false
+"#;
+
+/// Moves the entries of the job keys in `ARGV`, or all of them if none are
+/// given, from the processing hash `KEYS[1]` back to the front of the queues
+/// they were popped from. Returns how many were moved.
+const REQUEUE_PROCESSING_SCRIPT: &str = r#"
+local keys = ARGV
+if #keys == 0 then
+    keys = redis.call('HKEYS', KEYS[1])
+end
+local moved = 0
+for _, key in ipairs(keys) do
+    local held = redis.call('HGET', KEYS[1], key)
+    if held then
+        local popped = cjson.decode(held)
+        redis.call('RPUSH', popped.queue, popped.entry)
+        redis.call('HDEL', KEYS[1], key)
+        moved = moved + 1
+    end
+end
+return moved
+"#;
+
+/// Moves the entries of the queue `KEYS[1]` in language `ARGV[i]` to the
+/// right end of `KEYS[i + 1]`, where they are popped first, keeping their
+/// order. Entries in other languages, or that aren't entries, stay. Returns
+/// how many were moved.
+const ROUTE_BY_LANGUAGE_SCRIPT: &str = r#"
+local targets = {}
+for i, language in ipairs(ARGV) do
+    targets[language] = KEYS[i + 1]
+end
+local kept = {}
+local moved = 0
+for _, data in ipairs(redis.call('LRANGE', KEYS[1], 0, -1)) do
+    local ok, entry = pcall(cjson.decode, data)
+    local target = ok and type(entry) == 'table' and targets[entry.language]
+    if target then
+        redis.call('RPUSH', target, data)
+        moved = moved + 1
+    else
+        table.insert(kept, data)
+    end
+end
+if moved > 0 then
+    redis.call('DEL', KEYS[1])
+    for _, data in ipairs(kept) do
+        redis.call('RPUSH', KEYS[1], data)
+    end
+end
+return moved
+"#;
+
+/// Replaces the legacy entry `ARGV[1]` in queue `KEYS[1]` with the key-only
+/// entry `ARGV[2]`, wherever concurrent pushes and pops have moved it, and
+/// stores its job `ARGV[3]` under `KEYS[2]` if missing. Does nothing once the
+/// entry has been popped. Returns 1 if it was replaced.
+const REPLACE_ENTRY_SCRIPT: &str = r#"
+for index, data in ipairs(redis.call('LRANGE', KEYS[1], 0, -1)) do
+    if data == ARGV[1] then
+        redis.call('SET', KEYS[2], ARGV[3], 'NX')
+        redis.call('LSET', KEYS[1], index - 1, ARGV[2])
+        return 1
+    end
+end
+return 0
+"#;
+
+impl RedisClient {
+    /// One-time migration of `queue` from full serialized jobs to key-only
+    /// entries. Each legacy entry is swapped in place by value and its job
+    /// written under its key if missing, in one script. Safe to run on a live
+    /// queue and to run repeatedly. Returns how many entries were rewritten.
+    pub async fn migrate_queue(&self, queue: &str) -> StoreResult<usize> {
+        let queue = &self.key(queue);
+        let entries: Vec<Vec<u8>> = self
+            .run(Retry::Always, self.command_timeout(), |mut conn| async move {
+                conn.lrange(queue, 0, -1).await
+            })
+            .await?;
+
+        let mut migrated = 0;
+        for (index, data) in entries.iter().enumerate() {
+            if decode_entry(data).is_some() {
+                continue;
+            }
+            let job = match decode_job(data) {
+                Ok(job) => job,
+                Err(e) => {
+                    tracing::error!("Skipping undecodable entry {} in {}: {}", index, queue, e);
+                    continue;
+                }
+            };
+            let key = job.id.to_string();
+            let (job_key, entry, serialized) =
+                (&self.key(&key), &encode_entry(&QueueEntry::new(&key, &job))?, &encode_job(&job)?);
+
+            // Replacing an entry that is already replaced is a no-op
+            let replaced: i32 = self
+                .run(Retry::Always, self.command_timeout(), |mut conn| async move {
+                    redis::cmd("EVAL")
+                        .arg(REPLACE_ENTRY_SCRIPT)
+                        .arg(2)
+                        .arg(queue)
+                        .arg(job_key)
+                        .arg(data.as_slice())
+                        .arg(entry)
+                        .arg(serialized)
+                        .query_async(&mut conn)
+                        .await
+                })
+                .await?;
+            migrated += replaced as usize;
+        }
+
+        if migrated > 0 {
+            tracing::info!("Migrated {} queued jobs in {} to key-only entries", migrated, queue);
+        }
+        Ok(migrated)
+    }
+}
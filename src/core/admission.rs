@@ -0,0 +1,347 @@
+//! Admission control for `/create`.
+//!
+//! A submission is turned away when the queue is deeper than
+//! `admission.max_queue_depth` or would take longer than
+//! `admission.max_backlog` seconds to drain at the fleet's recent
+//! throughput. The throughput comes from the job counts workers report in
+//! their heartbeats, so it covers workers in other processes too. A queue
+//! nothing is draining only counts as a backlog while every live slot is
+//! busy, since an idle fleet has finished nothing yet either.
+
+use super::{config::AdmissionConfig, WorkerInfo};
+use crate::client::{Backend, StoreResult};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// How far back throughput is averaged.
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(60);
+/// How often the worker registry is sampled for throughput.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+/// Retry-After given when there's no throughput to estimate from yet.
+const DEFAULT_RETRY_AFTER: u64 = 5;
+const MAX_RETRY_AFTER: u64 = 300;
+
+pub enum Decision {
+    Admit,
+    Reject {
+        /// `queue_depth` or `backlog`, used as the metric label.
+        reason: &'static str,
+        /// Seconds until the backlog is expected to be back under the limit.
+        retry_after: u64,
+    },
+}
+
+pub struct Admission {
+    config: AdmissionConfig,
+    /// Every queue jobs wait in, whose lengths add up to the depth.
+    queues: Vec<String>,
+    heartbeat_ttl: Duration,
+    /// Live workers sampled over the window.
+    samples: Mutex<VecDeque<Sample>>,
+}
+
+struct Sample {
+    at: Instant,
+    /// Jobs each live worker had finished.
+    done: HashMap<String, u64>,
+    /// Slots of live workers not running a job.
+    free_slots: usize,
+}
+
+impl Admission {
+    pub fn new(config: AdmissionConfig, queues: Vec<String>, heartbeat_ttl: Duration) -> Self {
+        Self {
+            config,
+            queues,
+            heartbeat_ttl,
+            samples: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.max_queue_depth > 0 || self.config.max_backlog > 0
+    }
+
+    /// Decides whether a submission made with `api_key` is queued.
+    pub async fn check(&self, backend: &dyn Backend, api_key: Option<&str>) -> StoreResult<Decision> {
+        if !self.is_enabled() {
+            return Ok(Decision::Admit);
+        }
+        if api_key.is_some_and(|key| self.config.bypass_keys.iter().any(|k| k == key)) {
+            return Ok(Decision::Admit);
+        }
+
+        let depth = self.queue_depth(backend).await?;
+        let rate = self.throughput(backend).await?;
+        // Seconds until the queue is down to `limit` entries
+        let drain_time = |limit: u64| match rate.filter(|rate| *rate > 0.0) {
+            Some(rate) => ((depth.saturating_sub(limit) as f64 / rate).ceil() as u64)
+                .clamp(1, MAX_RETRY_AFTER),
+            None => DEFAULT_RETRY_AFTER,
+        };
+
+        let max_depth = self.config.max_queue_depth;
+        if max_depth > 0 && depth >= max_depth {
+            return Ok(Decision::Reject {
+                reason: "queue_depth",
+                retry_after: drain_time(max_depth - 1),
+            });
+        }
+
+        let max_backlog = self.config.max_backlog;
+        if let Some(rate) = rate.filter(|_| max_backlog > 0 && depth > 0) {
+            // Nothing finishing with every slot busy means the backlog never
+            // drains; with free slots the fleet is idle, not stuck
+            let stuck = rate == 0.0 && self.free_slots() == Some(0);
+            if stuck || (rate > 0.0 && depth as f64 / rate > max_backlog as f64) {
+                return Ok(Decision::Reject {
+                    reason: "backlog",
+                    retry_after: drain_time((max_backlog as f64 * rate) as u64),
+                });
+            }
+        }
+        Ok(Decision::Admit)
+    }
+
+    /// Jobs waiting across every queue.
+    pub async fn queue_depth(&self, backend: &dyn Backend) -> StoreResult<u64> {
+        let lens = backend.queue_lens(&self.queues).await?;
+        Ok(lens.into_iter().map(|len| len as u64).sum())
+    }
+
+    /// Jobs finished per second across the fleet over the last minute, or
+    /// `None` until samples span a sampling interval. Zero when no live
+    /// worker finished anything over them.
+    async fn throughput(&self, backend: &dyn Backend) -> StoreResult<Option<f64>> {
+        let stale = match self.samples.lock().unwrap().back() {
+            Some(sample) => sample.at.elapsed() >= SAMPLE_INTERVAL,
+            None => true,
+        };
+        if stale {
+            let workers = backend.list_workers().await?;
+            self.record(Instant::now(), &workers);
+        }
+
+        let samples = self.samples.lock().unwrap();
+        let (Some(first), Some(last)) = (samples.front(), samples.back()) else {
+            return Ok(None);
+        };
+        let elapsed = last.at.duration_since(first.at).as_secs_f64();
+        if elapsed < SAMPLE_INTERVAL.as_secs_f64() {
+            return Ok(None);
+        }
+        // Only workers seen at both ends count, so restarts don't skew it
+        let done: u64 = last
+            .done
+            .iter()
+            .filter_map(|(id, done)| Some(done.saturating_sub(*first.done.get(id)?)))
+            .sum();
+        Ok(Some(done as f64 / elapsed))
+    }
+
+    /// Free slots of the live workers as of the latest sample.
+    fn free_slots(&self) -> Option<usize> {
+        self.samples.lock().unwrap().back().map(|sample| sample.free_slots)
+    }
+
+    /// Adds the finished job counts and free slots of the live `workers` as
+    /// sampled at `now`.
+    fn record(&self, now: Instant, workers: &[WorkerInfo]) {
+        let live: Vec<&WorkerInfo> = workers
+            .iter()
+            .filter(|worker| worker.is_alive(self.heartbeat_ttl))
+            .collect();
+        let sample = Sample {
+            at: now,
+            done: live.iter().map(|worker| (worker.id.clone(), worker.jobs_done)).collect(),
+            free_slots: live
+                .iter()
+                .map(|worker| worker.concurrency.saturating_sub(worker.jobs.len()))
+                .sum(),
+        };
+
+        let mut samples = self.samples.lock().unwrap();
+        samples.push_back(sample);
+        while samples
+            .front()
+            .is_some_and(|sample| now.duration_since(sample.at) > THROUGHPUT_WINDOW)
+        {
+            samples.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{memory::MemoryClient, JobQueue};
+    use crate::core::{ActiveJob, Job, Language};
+    use std::collections::BTreeMap;
+
+    const QUEUE: &str = "jobs";
+
+    fn admission(max_queue_depth: u64, max_backlog: u64) -> Admission {
+        let config = AdmissionConfig {
+            max_queue_depth,
+            max_backlog,
+            bypass_keys: vec!["bypass".to_string()],
+            ..Default::default()
+        };
+        Admission::new(config, vec![QUEUE.to_string()], Duration::from_secs(30))
+    }
+
+    fn worker(id: &str, jobs_done: u64) -> WorkerInfo {
+        let mut worker = WorkerInfo::new(4, Vec::new(), BTreeMap::new());
+        worker.id = id.to_string();
+        worker.beat(Vec::new(), jobs_done);
+        worker
+    }
+
+    /// A worker running a job in each of its slots.
+    fn busy_worker(id: &str, jobs_done: u64) -> WorkerInfo {
+        let mut worker = worker(id, jobs_done);
+        let jobs = (0..worker.concurrency as u64)
+            .map(|n| ActiveJob {
+                id: n,
+                ..Default::default()
+            })
+            .collect();
+        worker.beat(jobs, jobs_done);
+        worker
+    }
+
+    /// Records `done_before` finished jobs ten seconds ago and `done_now`
+    /// now for one worker.
+    fn sample(admission: &Admission, done_before: u64, done_now: u64) {
+        let now = Instant::now();
+        admission.record(now - Duration::from_secs(10), &[worker("a", done_before)]);
+        admission.record(now, &[worker("a", done_now)]);
+    }
+
+    async fn queue(backend: &MemoryClient, jobs: usize) {
+        for n in 0..jobs {
+            let job = Job::new(format!("print({})", n), Language::from_name("python").unwrap());
+            backend.create_job(&n.to_string(), QUEUE, &job).await.unwrap();
+        }
+    }
+
+    fn rejected(decision: Decision) -> Option<(&'static str, u64)> {
+        match decision {
+            Decision::Admit => None,
+            Decision::Reject { reason, retry_after } => Some((reason, retry_after)),
+        }
+    }
+
+    #[tokio::test]
+    async fn admits_a_backlog_under_the_limit() {
+        let backend = MemoryClient::new();
+        let admission = admission(0, 10);
+        // 2 jobs a second, so 10 queued jobs take 5 seconds
+        sample(&admission, 0, 20);
+        queue(&backend, 10).await;
+
+        let decision = admission.check(&backend, None).await.unwrap();
+        assert_eq!(rejected(decision), None);
+    }
+
+    #[tokio::test]
+    async fn rejects_a_backlog_over_the_limit_until_it_drains() {
+        let backend = MemoryClient::new();
+        let admission = admission(0, 10);
+        // 1 job a second, so 25 queued jobs take 25 seconds, 15 over
+        sample(&admission, 0, 10);
+        queue(&backend, 25).await;
+
+        let decision = admission.check(&backend, None).await.unwrap();
+        assert_eq!(rejected(decision), Some(("backlog", 15)));
+    }
+
+    #[tokio::test]
+    async fn rejects_a_queue_nothing_is_draining_only_when_saturated() {
+        let backend = MemoryClient::new();
+        queue(&backend, 1).await;
+
+        // Idle, as after a cold start or a quiet period
+        let idle = admission(0, 10);
+        sample(&idle, 7, 7);
+        let decision = idle.check(&backend, None).await.unwrap();
+        assert_eq!(rejected(decision), None);
+
+        // Every slot busy and nothing finishing
+        let saturated = admission(0, 10);
+        let now = Instant::now();
+        saturated.record(now - Duration::from_secs(10), &[busy_worker("a", 7)]);
+        saturated.record(now, &[busy_worker("a", 7)]);
+        let decision = saturated.check(&backend, None).await.unwrap();
+        assert_eq!(rejected(decision), Some(("backlog", DEFAULT_RETRY_AFTER)));
+    }
+
+    #[tokio::test]
+    async fn admits_without_a_throughput_sample() {
+        let backend = MemoryClient::new();
+        let admission = admission(0, 10);
+        queue(&backend, 5).await;
+
+        let decision = admission.check(&backend, None).await.unwrap();
+        assert_eq!(rejected(decision), None);
+    }
+
+    #[tokio::test]
+    async fn admits_into_an_empty_queue_without_throughput() {
+        let backend = MemoryClient::new();
+        let admission = admission(0, 10);
+        sample(&admission, 7, 7);
+
+        let decision = admission.check(&backend, None).await.unwrap();
+        assert_eq!(rejected(decision), None);
+    }
+
+    #[tokio::test]
+    async fn rejects_at_the_depth_limit_with_time_to_drain_one() {
+        let backend = MemoryClient::new();
+        let admission = admission(5, 0);
+        // 0.5 jobs a second
+        sample(&admission, 0, 5);
+        queue(&backend, 5).await;
+
+        let decision = admission.check(&backend, None).await.unwrap();
+        assert_eq!(rejected(decision), Some(("queue_depth", 2)));
+    }
+
+    #[tokio::test]
+    async fn caps_retry_after() {
+        let backend = MemoryClient::new();
+        let admission = admission(1, 0);
+        sample(&admission, 0, 1);
+        queue(&backend, 100).await;
+
+        let decision = admission.check(&backend, None).await.unwrap();
+        assert_eq!(rejected(decision), Some(("queue_depth", MAX_RETRY_AFTER)));
+    }
+
+    #[tokio::test]
+    async fn lets_bypass_keys_through() {
+        let backend = MemoryClient::new();
+        let admission = admission(1, 0);
+        queue(&backend, 3).await;
+
+        let decision = admission.check(&backend, Some("bypass")).await.unwrap();
+        assert_eq!(rejected(decision), None);
+        let decision = admission.check(&backend, Some("other")).await.unwrap();
+        assert!(rejected(decision).is_some());
+    }
+
+    #[tokio::test]
+    async fn ignores_workers_missing_from_the_first_sample() {
+        let admission = admission(0, 10);
+        let now = Instant::now();
+        admission.record(now - Duration::from_secs(10), &[worker("a", 0)]);
+        admission.record(now, &[worker("a", 10), worker("b", 1000)]);
+
+        let rate = admission.throughput(&MemoryClient::new()).await.unwrap();
+        assert_eq!(rate, Some(1.0));
+    }
+}
@@ -0,0 +1,70 @@
+use serde::{Deserialize, Serialize};
+
+/// Resource usage of a single run of a job's program.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct RunSample {
+    pub time: f64,
+    pub wall_time: f64,
+    pub memory: u64,
+    pub exit_code: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct Summary {
+    pub min: f64,
+    pub median: f64,
+    pub mean: f64,
+    pub stddev: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct BenchmarkStats {
+    pub cpu_time: Summary,
+    pub wall_time: Summary,
+    pub memory: Summary,
+}
+
+impl Summary {
+    pub fn from_values(values: &[f64]) -> Self {
+        if values.is_empty() {
+            return Self::default();
+        }
+
+        let mut sorted = values.to_vec();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+
+        let n = sorted.len();
+        let median = if n.is_multiple_of(2) {
+            (sorted[n / 2 - 1] + sorted[n / 2]) / 2.0
+        } else {
+            sorted[n / 2]
+        };
+        let mean = sorted.iter().sum::<f64>() / n as f64;
+        // Population standard deviation: the samples are every run we made.
+        let variance = sorted.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n as f64;
+
+        Self {
+            min: sorted[0],
+            median,
+            mean,
+            stddev: variance.sqrt(),
+        }
+    }
+}
+
+impl BenchmarkStats {
+    pub fn from_samples(samples: &[RunSample]) -> Self {
+        let cpu: Vec<f64> = samples.iter().map(|s| s.time).collect();
+        let wall: Vec<f64> = samples.iter().map(|s| s.wall_time).collect();
+        let memory: Vec<f64> = samples.iter().map(|s| s.memory as f64).collect();
+
+        Self {
+            cpu_time: Summary::from_values(&cpu),
+            wall_time: Summary::from_values(&wall),
+            memory: Summary::from_values(&memory),
+        }
+    }
+}
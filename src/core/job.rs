@@ -1,13 +1,19 @@
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use uuid::Uuid;
 use std::{
+    collections::HashMap,
     fmt::Display,
     time::{SystemTime, UNIX_EPOCH},
 };
 
-use super::{ExecutionSettings, Language};
+use super::{
+    tenant::DEFAULT_TENANT,
+    BenchmarkStats, ExecutionSettings, Language, RunSample,
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Job {
     pub id: u64,
     pub source_code: String,
@@ -21,9 +27,74 @@ pub struct Job {
     pub finished_at: Option<i64>,
     pub output: JobOutput,
     pub number_of_runs: u8,
+    pub benchmark: bool,
+    pub priority: Priority,
+    /// Tenant of the client that submitted the job.
+    pub tenant: String,
+    /// Time after which the job expires instead of running, in
+    /// milliseconds since the epoch.
+    pub deadline: Option<i64>,
+    /// W3C trace context of the request that submitted the job.
+    pub trace_context: HashMap<String, String>,
+    pub timings: JobTimings,
+    /// Hash of everything that decides the job's result, set when the result
+    /// cache is on. See [`Job::content_hash`].
+    pub fingerprint: Option<String>,
+    /// Whether the result was copied from an identical submission instead of
+    /// coming from running this job.
+    pub cached: bool,
+}
+
+/// When each step of a job happened, in milliseconds since the epoch.
+/// Compile and run times cover the judged run, not benchmark repeats.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct JobTimings {
+    pub queued_at: Option<i64>,
+    pub dequeued_at: Option<i64>,
+    pub compile_started_at: Option<i64>,
+    pub compile_finished_at: Option<i64>,
+    pub run_started_at: Option<i64>,
+    pub run_finished_at: Option<i64>,
+    pub finished_at: Option<i64>,
+}
+
+/// Workers take jobs from higher priorities first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+impl Priority {
+    /// Highest first, the order workers serve them in.
+    pub const ALL: [Priority; 3] = [Priority::High, Priority::Normal, Priority::Low];
+
+    /// This priority, lowered to `max` if it is above it.
+    pub fn at_most(self, max: Priority) -> Priority {
+        let rank = |priority| Priority::ALL.iter().position(|p| *p == priority);
+        if rank(self) < rank(max) {
+            max
+        } else {
+            self
+        }
+    }
+
+    /// Prefix of the priority's queues; normal jobs use the original `jobs`.
+    pub fn queue(&self) -> &'static str {
+        match self {
+            Priority::High => "jobs:high",
+            Priority::Normal => "jobs",
+            Priority::Low => "jobs:low",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
 pub struct JobOutput {
     pub stdout: Option<String>,
     pub stderr: Option<String>,
@@ -32,6 +103,8 @@ pub struct JobOutput {
     pub memory: Option<u64>,
     pub exit_code: Option<i32>,
     pub message: Option<String>,
+    pub runs: Vec<RunSample>,
+    pub benchmark: Option<BenchmarkStats>,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -45,9 +118,23 @@ pub enum JobStatus {
     RuntimeError(String),
     InternalError,
     ExecFormatError,
+    /// The deadline passed before a worker got to the job.
+    Expired,
 }
 
 impl JobStatus {
+    /// Whether the status is a verdict on the program itself, which an
+    /// identical submission would get too, rather than a judge failure.
+    /// Time limits depend on how loaded the worker was, and compile errors
+    /// on the worker's compiler version, which the cache key doesn't hold,
+    /// so neither is reused.
+    pub fn is_cacheable(&self) -> bool {
+        matches!(
+            self,
+            JobStatus::Accepted | JobStatus::WrongAnswer | JobStatus::RuntimeError(_)
+        )
+    }
+
     pub fn id(&self) -> i32 {
         match self {
             JobStatus::Queued => 1,
@@ -66,6 +153,7 @@ impl JobStatus {
             },
             JobStatus::InternalError => 13,
             JobStatus::ExecFormatError => 14,
+            JobStatus::Expired => 15,
         }
     }
 }
@@ -82,10 +170,40 @@ impl Display for JobStatus {
             JobStatus::RuntimeError(e) => write!(f, "Runtime Error: ({})", e),
             JobStatus::InternalError => write!(f, "Internal Error"),
             JobStatus::ExecFormatError => write!(f, "Exec Format Error"),
+            JobStatus::Expired => write!(f, "Expired"),
         }
     }
 }
 
+/// Queue holding jobs of `tenant` in `language` at `priority`. An empty
+/// language names the tenant's queue from before jobs were routed by
+/// language, which workers empty into the others when they start.
+pub fn queue_name(priority: Priority, tenant: &str, language: &str) -> String {
+    let mut name = priority.queue().to_string();
+    if !tenant.is_empty() && tenant != DEFAULT_TENANT {
+        name.push_str(":tenant:");
+        name.push_str(tenant);
+    }
+    match language.is_empty() {
+        true => name,
+        false => language_queue(&name, language),
+    }
+}
+
+/// The queue of `language` jobs that were sent to `queue` before jobs were
+/// routed by language.
+pub fn language_queue(queue: &str, language: &str) -> String {
+    format!("{}:lang:{}", queue, language)
+}
+
+/// Milliseconds since the epoch, the resolution of [`JobTimings`].
+pub fn unix_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64
+}
+
 impl Job {
     pub fn new(source_code: String, language: Language) -> Self {
         Self {
@@ -106,6 +224,87 @@ impl Job {
         self
     }
 
+    /// Runs the program `number_of_runs` times and reports timing statistics.
+    /// The verdict is still taken from the first run.
+    pub fn with_benchmark(mut self, number_of_runs: Option<u8>) -> Self {
+        self.benchmark = true;
+        if let Some(runs) = number_of_runs {
+            self.number_of_runs = runs.max(1);
+        }
+        self
+    }
+
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn with_tenant(mut self, tenant: &str) -> Self {
+        self.tenant = tenant.to_string();
+        self
+    }
+
+    /// Queue the job waits in, from its priority, tenant and language.
+    pub fn queue(&self) -> String {
+        queue_name(self.priority, &self.tenant, &self.language.name)
+    }
+
+    /// Expires the job unless a worker picks it up by `deadline`, in
+    /// milliseconds since the epoch.
+    pub fn with_deadline(mut self, deadline: i64) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Whether the job's deadline has passed at `now`, in milliseconds since
+    /// the epoch.
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.deadline.is_some_and(|deadline| deadline < now)
+    }
+
+    /// Erases the submitted source, test data and program output, keeping
+    /// only the verdict and resource usage.
+    pub fn purge(&mut self) {
+        self.source_code.clear();
+        self.stdin.clear();
+        self.expected_output.clear();
+        self.output.stdout = None;
+        self.output.stderr = None;
+        self.output.compile_output = None;
+    }
+
+    pub fn is_finished(&self) -> bool {
+        !matches!(self.status, JobStatus::Queued | JobStatus::Processing)
+    }
+
+    /// Hex SHA-256 of the tenant, language configuration, source, stdin,
+    /// expected output and limits. Jobs with the same hash get the same
+    /// verdict. The tenant keeps one client's results from answering, or
+    /// revealing, another's submissions.
+    pub fn content_hash(&self) -> String {
+        let inputs = (
+            &self.tenant,
+            &self.language,
+            &self.source_code,
+            &self.stdin,
+            &self.expected_output,
+            &self.settings,
+        );
+        let bytes = serde_json::to_vec(&inputs).expect("job inputs serialize");
+        Sha256::digest(bytes)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// Takes the verdict and output of `result`, an identical job that ran.
+    pub fn copy_result(&mut self, result: &Job) {
+        self.status = result.status.clone();
+        self.started_at = result.started_at;
+        self.output = result.output.clone();
+        self.cached = true;
+    }
+
     pub fn set_limits(
         mut self,
         cpu_time_limit: f64,
@@ -139,6 +338,14 @@ impl Default for Job {
             finished_at: None,
             output: JobOutput::default(),
             number_of_runs: 5,
+            benchmark: false,
+            priority: Priority::Normal,
+            tenant: DEFAULT_TENANT.to_string(),
+            deadline: None,
+            trace_context: HashMap::new(),
+            timings: JobTimings::default(),
+            fingerprint: None,
+            cached: false,
         }
     }
 }
@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Language {
     pub name: String,
     pub source_file: String,
@@ -9,6 +10,54 @@ pub struct Language {
     pub is_compiled: bool,
 }
 
+impl Language {
+    /// Every language with a built-in toolchain configuration.
+    pub const NAMES: [&'static str; 5] = ["python", "cpp", "javascript", "java", "sql"];
+
+    /// The built-in toolchain configuration for a language name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let language = match name {
+            "python" => Language {
+                name: "python".to_string(),
+                source_file: "main.py".to_string(),
+                compile_cmd: None,
+                run_cmd: "/usr/bin/python3 main.py".to_string(),
+                is_compiled: false,
+            },
+            "cpp" => Language {
+                name: "cpp".to_string(),
+                source_file: "main.cpp".to_string(),
+                compile_cmd: Some("/usr/bin/g++ -O0 -Wall -Wextra -Werror -Wpedantic -Wfatal-errors main.cpp".to_string()),
+                run_cmd: "./a.out".to_string(),
+                is_compiled: true,
+            },
+            "javascript" => Language {
+                name: "javascript".to_string(),
+                source_file: "main.js".to_string(),
+                compile_cmd: None,
+                run_cmd: "/usr/bin/node main.js".to_string(),
+                is_compiled: false,
+            },
+            "java" => Language {
+                name: "java".to_string(),
+                source_file: "Main.java".to_string(),
+                compile_cmd: Some("/usr/bin/javac Main.java".to_string()),
+                run_cmd: "/usr/bin/java Main".to_string(),
+                is_compiled: false,
+            },
+            "sql" => Language {
+                name: "sql".to_string(),
+                source_file: "main.sql".to_string(),
+                compile_cmd: None,
+                run_cmd: "sqlite3".to_string(),
+                is_compiled: false,
+            },
+            _ => return None,
+        };
+        Some(language)
+    }
+}
+
 impl Default for Language {
     fn default() -> Self {
         Self {
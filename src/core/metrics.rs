@@ -0,0 +1,223 @@
+//! Prometheus metrics, rendered in the text exposition format at `/metrics`.
+//!
+//! Counters and histograms are per process; scrape every server and worker.
+//! Queue depth and the fleet gauges are read from the store at scrape time,
+//! so any server reports them for the whole fleet.
+
+use super::JobStatus;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use std::{sync::LazyLock, time::Duration};
+
+/// Buckets for queue wait, which runs from milliseconds to minutes under load.
+const QUEUE_WAIT_BUCKETS: &[f64] = &[
+    0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0,
+];
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub struct Metrics {
+    registry: Registry,
+    /// Entries waiting in each queue.
+    pub queue_depth: IntGaugeVec,
+    /// Jobs waiting in each tenant's queues, and jobs each tenant has
+    /// running across the fleet as of the workers' last heartbeats.
+    pub tenant_queue_depth: IntGaugeVec,
+    pub tenant_jobs_running: IntGaugeVec,
+    /// Jobs waiting in each language's queues.
+    pub language_queue_depth: IntGaugeVec,
+    /// Jobs this process is executing.
+    pub jobs_in_flight: IntGauge,
+    /// Live workers, their slots and busy slots, from the worker registry.
+    pub workers: IntGauge,
+    pub worker_slots: IntGauge,
+    pub worker_slots_busy: IntGauge,
+    /// Jobs accepted by `/create`, by language.
+    pub jobs_submitted: IntCounterVec,
+    /// Submissions turned away by admission control, by reason.
+    pub jobs_rejected: IntCounterVec,
+    /// Jobs judged, by language and verdict.
+    pub jobs_finished: IntCounterVec,
+    /// Submissions checked against the result cache, by outcome: `hit`,
+    /// `coalesced` onto a running identical job, or `miss`.
+    pub result_cache: IntCounterVec,
+    /// Compiles looked up in the artifact cache, by outcome: `hit`, `miss`,
+    /// or `evicted` for entries dropped to stay under the size limit.
+    pub artifact_cache: IntCounterVec,
+    /// Size of the artifact cache as of its last eviction pass.
+    pub artifact_cache_bytes: IntGauge,
+    /// Duration of each execution phase, in seconds.
+    pub phase_duration: HistogramVec,
+    /// Time from submission until a worker picked the job up, in seconds.
+    pub queue_wait: HistogramVec,
+    /// Whether each language passed its last canary on this worker, and
+    /// how many canary checks it has failed.
+    pub language_healthy: IntGaugeVec,
+    pub canary_failures: IntCounterVec,
+    pub box_init_failures: IntCounter,
+    pub redis_errors: IntCounter,
+    pub redis_retries: IntCounter,
+    pub redis_breaker_open: IntGauge,
+}
+
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+        macro_rules! register {
+            ($metric:expr) => {{
+                let metric = $metric.expect("Invalid metric definition");
+                registry
+                    .register(Box::new(metric.clone()))
+                    .expect("Metric registered twice");
+                metric
+            }};
+        }
+
+        Self {
+            queue_depth: register!(IntGaugeVec::new(
+                Opts::new("flash_queue_depth", "Jobs waiting in the queue"),
+                &["queue"]
+            )),
+            tenant_queue_depth: register!(IntGaugeVec::new(
+                Opts::new("flash_tenant_queue_depth", "Jobs waiting in a tenant's queues"),
+                &["tenant"]
+            )),
+            tenant_jobs_running: register!(IntGaugeVec::new(
+                Opts::new("flash_tenant_jobs_running", "Jobs of a tenant running across the fleet"),
+                &["tenant"]
+            )),
+            language_queue_depth: register!(IntGaugeVec::new(
+                Opts::new("flash_language_queue_depth", "Jobs waiting in a language's queues"),
+                &["language"]
+            )),
+            jobs_in_flight: register!(IntGauge::new(
+                "flash_jobs_in_flight",
+                "Jobs this process is executing"
+            )),
+            workers: register!(IntGauge::new("flash_workers", "Live workers in the fleet")),
+            worker_slots: register!(IntGauge::new(
+                "flash_worker_slots",
+                "Job slots across live workers"
+            )),
+            worker_slots_busy: register!(IntGauge::new(
+                "flash_worker_slots_busy",
+                "Busy job slots across live workers, as of their last heartbeat"
+            )),
+            jobs_submitted: register!(IntCounterVec::new(
+                Opts::new("flash_jobs_submitted_total", "Jobs submitted"),
+                &["language"]
+            )),
+            jobs_rejected: register!(IntCounterVec::new(
+                Opts::new("flash_jobs_rejected_total", "Submissions rejected by admission control"),
+                &["reason"]
+            )),
+            jobs_finished: register!(IntCounterVec::new(
+                Opts::new("flash_jobs_finished_total", "Jobs judged"),
+                &["language", "verdict"]
+            )),
+            result_cache: register!(IntCounterVec::new(
+                Opts::new("flash_result_cache_total", "Submissions checked against the result cache"),
+                &["outcome"]
+            )),
+            artifact_cache: register!(IntCounterVec::new(
+                Opts::new("flash_artifact_cache_total", "Compiled artifact cache lookups and evictions"),
+                &["outcome"]
+            )),
+            artifact_cache_bytes: register!(IntGauge::new(
+                "flash_artifact_cache_bytes",
+                "Bytes used by the compiled artifact cache"
+            )),
+            phase_duration: register!(HistogramVec::new(
+                HistogramOpts::new(
+                    "flash_phase_duration_seconds",
+                    "Duration of each job execution phase"
+                ),
+                &["phase"]
+            )),
+            queue_wait: register!(HistogramVec::new(
+                HistogramOpts::new(
+                    "flash_queue_wait_seconds",
+                    "Time jobs spent queued before a worker picked them up"
+                )
+                .buckets(QUEUE_WAIT_BUCKETS.to_vec()),
+                &["language"]
+            )),
+            language_healthy: register!(IntGaugeVec::new(
+                Opts::new("flash_language_healthy", "Whether a language passed its last canary check"),
+                &["language"]
+            )),
+            canary_failures: register!(IntCounterVec::new(
+                Opts::new("flash_canary_failures_total", "Failed canary checks"),
+                &["language"]
+            )),
+            box_init_failures: register!(IntCounter::new(
+                "flash_box_init_failures_total",
+                "Isolate boxes that failed to initialize"
+            )),
+            redis_errors: register!(IntCounter::new(
+                "flash_redis_errors_total",
+                "Failed Redis commands and connections"
+            )),
+            redis_retries: register!(IntCounter::new(
+                "flash_redis_retries_total",
+                "Redis commands retried after a transient failure"
+            )),
+            redis_breaker_open: register!(IntGauge::new(
+                "flash_redis_breaker_open",
+                "Whether the Redis circuit breaker is failing commands fast"
+            )),
+            registry,
+        }
+    }
+
+    pub fn observe_phase(&self, phase: &str, duration: Duration) {
+        self.phase_duration
+            .with_label_values(&[phase])
+            .observe(duration.as_secs_f64());
+    }
+
+    pub fn record_verdict(&self, language: &str, status: &JobStatus) {
+        self.jobs_finished
+            .with_label_values(&[language, verdict_label(status)])
+            .inc();
+    }
+
+    /// Every metric in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("Failed to encode metrics");
+        String::from_utf8(buffer).expect("Metrics are not UTF-8")
+    }
+}
+
+/// Verdicts as label values. Runtime errors are split by signal like the
+/// status ids are.
+fn verdict_label(status: &JobStatus) -> &'static str {
+    match status {
+        JobStatus::Queued => "queued",
+        JobStatus::Processing => "processing",
+        JobStatus::Accepted => "accepted",
+        JobStatus::WrongAnswer => "wrong_answer",
+        JobStatus::TimeLimitExceeded => "time_limit_exceeded",
+        JobStatus::CompilationError => "compilation_error",
+        JobStatus::RuntimeError(e) => match e.as_str() {
+            "SIGSEGV" => "runtime_error_sigsegv",
+            "SIGXFSZ" => "runtime_error_sigxfsz",
+            "SIGFPE" => "runtime_error_sigfpe",
+            "SIGABRT" => "runtime_error_sigabrt",
+            "NZEC" => "runtime_error_nzec",
+            _ => "runtime_error_other",
+        },
+        JobStatus::InternalError => "internal_error",
+        JobStatus::ExecFormatError => "exec_format_error",
+        JobStatus::Expired => "expired",
+    }
+}
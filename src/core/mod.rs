@@ -1,8 +1,18 @@
+pub mod admission;
+pub mod benchmark;
+pub mod config;
 pub mod job;
-pub mod language;
-pub mod settings;
-pub mod server;
+pub mod language;
+pub mod metrics;
+pub mod settings;
+pub mod telemetry;
+pub mod tenant;
+pub mod server;
+pub mod worker;
 
+pub use benchmark::*;
+pub use config::*;
 pub use job::*;
 pub use language::*;
-pub use settings::*;
\ No newline at end of file
+pub use settings::*;
+pub use worker::*;
\ No newline at end of file
@@ -1,25 +1,103 @@
 use crate::{
-    client::redis::RedisClient,
-    core::{job::Job, language::Language, settings::ExecutionSettings},
-    utils::utils::{check_job, create_job},
+    client::{archive::JobArchive, Backend, Claim, StoreResult},
+    core::{
+        admission::{Admission, Decision},
+        config::Config,
+        job::{queue_name, unix_millis, Job, Priority},
+        tenant::Tenants,
+        language::Language,
+        metrics::metrics,
+        settings::ExecutionSettings,
+        telemetry, WorkerInfo,
+    },
+    utils::utils::{create_job, run_waiters},
     vendors::debugger,
 };
 use axum::{
-    extract::{Json, Path, State},
-    http::StatusCode,
+    extract::{Json, Path, Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
     routing::{get, post},
     Router,
 };
 use serde_json::json;
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tokio::sync::watch;
+use tracing::Instrument;
 
-pub fn server(redis_client: RedisClient) -> Router {
-    Router::new()
+#[derive(Clone)]
+pub struct AppState {
+    pub backend: Arc<dyn Backend>,
+    pub archive: Option<JobArchive>,
+    pub config: Arc<Config>,
+    pub admission: Arc<Admission>,
+    pub tenants: Arc<Tenants>,
+    /// Set to true to shut the process down gracefully.
+    pub drain: Arc<watch::Sender<bool>>,
+}
+
+/// Header identifying the client submitting a job.
+const API_KEY_HEADER: &str = "x-api-key";
+/// Longest `/health/ready` waits for the store to answer.
+const READY_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub fn server(state: AppState) -> Router {
+    let state = Arc::new(state);
+    let router = Router::new()
         .route("/create", post(handle_create))
         .route("/check/:job_id", get(handle_check))
+        .route("/jobs/:job_id/purge", post(handle_purge))
         .route("/debug", post(handle_debug))
-        .route("/health",get(handle_get))
-        .with_state(Arc::new(redis_client))
+        .route("/health",get(handle_health))
+        .route("/health/live", get(handle_live))
+        .route("/health/ready", get(handle_ready))
+        .route("/languages", get(handle_languages))
+        .route("/metrics", get(handle_metrics));
+
+    // Without a token the admin endpoints aren't served at all
+    let router = match state.config.server.admin_token {
+        Some(_) => router.merge(
+            Router::new()
+                .route("/admin/config", get(handle_config))
+                .route("/admin/workers", get(handle_workers))
+                .route("/admin/drain", post(handle_drain))
+                .route_layer(middleware::from_fn_with_state(Arc::clone(&state), require_admin)),
+        ),
+        None => router,
+    };
+    router.with_state(state)
+}
+
+/// Lets through requests whose `Authorization` header carries the admin
+/// token as a bearer token, and answers `401` to the rest.
+async fn require_admin(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    request: Request,
+    next: Next,
+) -> Response {
+    let expected = state.config.server.admin_token.as_deref().unwrap_or_default();
+    let given = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match given {
+        Some(given) if !expected.is_empty() && constant_time_eq(given.as_bytes(), expected.as_bytes()) => {
+            next.run(request).await
+        }
+        _ => (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+        )
+            .into_response(),
+    }
+}
+
+/// Compares without returning early, so the time taken doesn't tell how
+/// much of a guessed token was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
 }
 
 #[derive(serde::Deserialize)]
@@ -31,65 +109,319 @@ struct CreateJobRequest {
     time_limit: Option<f64>,
     memory_limit: Option<u64>,
     stack_limit: Option<u64>,
+    benchmark: Option<bool>,
+    number_of_runs: Option<u8>,
+    /// Capped at the tenant's `max_priority`.
+    priority: Option<Priority>,
+    /// Time after which the job expires instead of running, in
+    /// milliseconds since the epoch.
+    deadline: Option<i64>,
+}
+
+/// Overall status, and the status of each language as in `/languages`.
+/// Degraded while some worker's canary for a language fails.
+async fn handle_health(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
+    let workers = match live_workers(&state).await {
+        Ok(workers) => workers,
+        Err(e) => {
+            tracing::error!("Failed to read worker registry: {}", e);
+            return Json(json!({ "status": "degraded", "error": "worker registry unavailable" }));
+        }
+    };
+
+    let mut degraded = false;
+    let mut languages = serde_json::Map::new();
+    for name in Language::NAMES {
+        let status = language_health(name, &workers)["status"].clone();
+        degraded |= status == "failing" || status == "degraded";
+        languages.insert(name.to_string(), status);
+    }
+    Json(json!({
+        "status": if degraded { "degraded" } else { "ok" },
+        "languages": languages,
+    }))
+}
+
+/// Answers as long as the process is serving requests.
+async fn handle_live() -> Json<serde_json::Value> {
+    Json(json!({ "status": "ok" }))
+}
+
+/// Whether this server can take jobs: the store answers and the server
+/// isn't draining. Answers `503` when not, and reports each component either
+/// way. Every check gets `READY_TIMEOUT`, and they run at once. A queue over
+/// the admission limit and a fleet without live workers are reported as
+/// degraded without failing readiness: `/create` already sheds the former,
+/// and jobs queued in the latter wait for the workers to come back.
+async fn handle_ready(State(state): State<Arc<AppState>>) -> (StatusCode, Json<serde_json::Value>) {
+    let timed_out = || json!({ "status": "down", "error": format!("no answer within {:?}", READY_TIMEOUT) });
+    let started = std::time::Instant::now();
+    let (ping, depth, workers) = tokio::join!(
+        tokio::time::timeout(READY_TIMEOUT, state.backend.ping()),
+        tokio::time::timeout(READY_TIMEOUT, state.admission.queue_depth(state.backend.as_ref())),
+        tokio::time::timeout(READY_TIMEOUT, live_workers(&state)),
+    );
+
+    let store = match ping {
+        Ok(Ok(())) => json!({ "status": "ok", "latency_ms": started.elapsed().as_millis() as u64 }),
+        Ok(Err(e)) => json!({ "status": "down", "error": e.to_string() }),
+        Err(_) => timed_out(),
+    };
+
+    let queue = match depth {
+        Ok(Ok(depth)) => {
+            let limit = state.config.admission.max_queue_depth;
+            let status = if limit > 0 && depth >= limit { "degraded" } else { "ok" };
+            json!({ "status": status, "depth": depth })
+        }
+        Ok(Err(e)) => json!({ "status": "down", "error": e.to_string() }),
+        Err(_) => timed_out(),
+    };
+
+    let workers = match workers {
+        Ok(Ok(workers)) => {
+            let slots: usize = workers.iter().map(|worker| worker.concurrency).sum();
+            let busy: usize = workers.iter().map(|worker| worker.jobs.len()).sum();
+            let status = if workers.is_empty() { "degraded" } else { "ok" };
+            json!({ "status": status, "live": workers.len(), "slots": slots, "busy_slots": busy })
+        }
+        Ok(Err(e)) => json!({ "status": "down", "error": e.to_string() }),
+        Err(_) => timed_out(),
+    };
+
+    let draining = *state.drain.borrow();
+    let ready = !draining && [&store, &queue, &workers].iter().all(|c| c["status"] != "down");
+    let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (
+        status,
+        Json(json!({
+            "status": if ready { "ready" } else { "not_ready" },
+            "draining": draining,
+            "components": { "store": store, "queue": queue, "workers": workers },
+        })),
+    )
 }
 
-async fn handle_get()->String{
-    "ok".to_string()
+/// Supported languages with their toolchains and canary health.
+async fn handle_languages(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let workers = live_workers(&state)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let languages: Vec<serde_json::Value> = Language::NAMES
+        .iter()
+        .map(|name| language_health(name, &workers))
+        .collect();
+    Ok(Json(json!({ "languages": languages })))
+}
+
+/// A language's status across `workers`: `unavailable` when none runs it,
+/// `failing` when its canary fails on all that do, `degraded` when it fails
+/// on some, and `healthy` otherwise.
+fn language_health(name: &str, workers: &[WorkerInfo]) -> serde_json::Value {
+    let language = Language::from_name(name).unwrap_or_default();
+    let running: Vec<&WorkerInfo> = workers
+        .iter()
+        .filter(|worker| worker.languages.is_empty() || worker.languages.iter().any(|l| l == name))
+        .collect();
+    let serving = running.iter().filter(|worker| worker.serves(name)).count();
+    let status = if running.is_empty() {
+        "unavailable"
+    } else if serving == 0 {
+        "failing"
+    } else if serving < running.len() {
+        "degraded"
+    } else {
+        "healthy"
+    };
+
+    let checks: Vec<serde_json::Value> = running
+        .iter()
+        .filter_map(|worker| {
+            let canary = worker.canaries.get(name)?;
+            let mut check = json!(canary);
+            check["worker"] = json!(worker.id);
+            Some(check)
+        })
+        .collect();
+
+    json!({
+        "name": name,
+        "source_file": language.source_file,
+        "compiled": language.compile_cmd.is_some(),
+        "status": status,
+        "workers": running.len(),
+        "serving": serving,
+        "checks": checks,
+    })
+}
+
+/// Workers that have heartbeated within the TTL.
+async fn live_workers(state: &AppState) -> StoreResult<Vec<WorkerInfo>> {
+    let ttl = state.config.heartbeat_ttl();
+    let mut workers = state.backend.list_workers().await?;
+    workers.retain(|worker| worker.is_alive(ttl));
+    Ok(workers)
+}
+
+async fn handle_config(State(state): State<Arc<AppState>>) -> Json<Config> {
+    Json(state.config.redacted())
+}
+
+/// Router that only serves `/metrics`, for processes without the API.
+pub fn metrics_server() -> Router {
+    Router::new().route("/metrics", get(render_metrics))
+}
+
+async fn render_metrics() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics().render(),
+    )
+}
+
+/// Refreshes the gauges read from the store, then renders every metric.
+async fn handle_metrics(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let metrics = metrics();
+    // The empty language is the queue from before routing by language
+    let queues: Vec<(&str, &str, String)> = state
+        .tenants
+        .names()
+        .flat_map(|tenant| Priority::ALL.map(|priority| (tenant, priority)))
+        .flat_map(|(tenant, priority)| {
+            Language::NAMES
+                .into_iter()
+                .chain([""])
+                .map(move |language| (tenant, language, queue_name(priority, tenant, language)))
+        })
+        .collect();
+    let names: Vec<String> = queues.iter().map(|(_, _, queue)| queue.clone()).collect();
+    match state.backend.queue_lens(&names).await {
+        Ok(lens) => {
+            let mut tenant_depth: HashMap<&str, i64> = HashMap::new();
+            let mut language_depth: HashMap<&str, i64> = HashMap::new();
+            for ((tenant, language, queue), depth) in queues.iter().zip(lens) {
+                metrics.queue_depth.with_label_values(&[queue]).set(depth as i64);
+                *tenant_depth.entry(tenant).or_default() += depth as i64;
+                *language_depth.entry(language).or_default() += depth as i64;
+            }
+            for tenant in state.tenants.names() {
+                let depth = tenant_depth.get(tenant).copied().unwrap_or(0);
+                metrics.tenant_queue_depth.with_label_values(&[tenant]).set(depth);
+            }
+            for language in Language::NAMES {
+                let depth = language_depth.get(language).copied().unwrap_or(0);
+                metrics.language_queue_depth.with_label_values(&[language]).set(depth);
+            }
+        }
+        Err(e) => tracing::error!("Failed to read queue depths: {}", e),
+    }
+    match state.backend.list_workers().await {
+        Ok(workers) => {
+            let ttl = state.config.heartbeat_ttl();
+            let live: Vec<_> = workers.iter().filter(|worker| worker.is_alive(ttl)).collect();
+            for tenant in state.tenants.names() {
+                let running = live
+                    .iter()
+                    .flat_map(|worker| &worker.jobs)
+                    .filter(|job| job.tenant == tenant)
+                    .count();
+                metrics.tenant_jobs_running.with_label_values(&[tenant]).set(running as i64);
+            }
+            metrics.workers.set(live.len() as i64);
+            metrics
+                .worker_slots
+                .set(live.iter().map(|worker| worker.concurrency as i64).sum());
+            metrics
+                .worker_slots_busy
+                .set(live.iter().map(|worker| worker.jobs.len() as i64).sum());
+        }
+        Err(e) => tracing::error!("Failed to read worker registry: {}", e),
+    }
+    render_metrics().await
 }
 
+/// Live workers with their busy slots and running jobs.
+async fn handle_workers(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let mut workers = live_workers(&state)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    workers.sort_by(|a, b| a.id.cmp(&b.id));
+
+    let slots: usize = workers.iter().map(|worker| worker.concurrency).sum();
+    let busy: usize = workers.iter().map(|worker| worker.jobs.len()).sum();
+    let workers: Vec<serde_json::Value> = workers
+        .into_iter()
+        .map(|worker| {
+            let busy = worker.jobs.len();
+            let mut value = json!(worker);
+            value["busy"] = json!(busy);
+            value
+        })
+        .collect();
+
+    Ok(Json(json!({
+        "workers": workers,
+        "slots": slots,
+        "busy": busy,
+    })))
+}
+
+/// Shuts this process down as SIGTERM would: the server stops accepting
+/// connections and local workers finish or requeue their jobs.
+async fn handle_drain(State(state): State<Arc<AppState>>) -> (StatusCode, Json<serde_json::Value>) {
+    let already = state.drain.send_replace(true);
+    if !already {
+        tracing::info!("Drain requested through the admin API");
+    }
+    (StatusCode::ACCEPTED, Json(json!({ "status": "draining" })))
+}
+
+#[tracing::instrument(name = "POST /create", skip_all, fields(language = %payload.language))]
 async fn handle_create(
-    State(redis): State<Arc<RedisClient>>,
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
     Json(payload): Json<CreateJobRequest>,
-) -> Result<Json<serde_json::Value>, StatusCode> {
+) -> Result<Json<serde_json::Value>, Response> {
+    if *state.drain.borrow() {
+        return Err(StatusCode::SERVICE_UNAVAILABLE.into_response());
+    }
+
+    let api_key = headers.get(API_KEY_HEADER).and_then(|key| key.to_str().ok());
+    match state.admission.check(state.backend.as_ref(), api_key).await {
+        Ok(Decision::Admit) => {}
+        Ok(Decision::Reject { reason, retry_after }) => {
+            metrics().jobs_rejected.with_label_values(&[reason]).inc();
+            let status = StatusCode::from_u16(state.config.admission.status)
+                .unwrap_or(StatusCode::SERVICE_UNAVAILABLE);
+            return Err((
+                status,
+                [(header::RETRY_AFTER, retry_after.to_string())],
+                Json(json!({ "status": "rejected", "reason": reason, "retry_after": retry_after })),
+            )
+                .into_response());
+        }
+        // Shedding load is best effort; the enqueue below reports a broken store
+        Err(e) => tracing::error!("Admission check failed: {}", e),
+    }
+
     // let exact_current_time = std::time::SystemTime::now()
     //     .duration_since(std::time::UNIX_EPOCH)
     //     .unwrap()
     //     .as_micros();
     // println!("request received at {}", exact_current_time);
 
-    let language = match payload.language.as_str() {
-        "python" => Language {
-            name: "python".to_string(),
-            source_file: "main.py".to_string(),
-            compile_cmd: None,
-            run_cmd: "/usr/bin/python3 main.py".to_string(),
-            is_compiled: false,
-        },
-        "cpp" => Language {
-            name: "cpp".to_string(),
-            source_file: "main.cpp".to_string(),
-            compile_cmd: Some("/usr/bin/g++ -O0 -Wall -Wextra -Werror -Wpedantic -Wfatal-errors main.cpp".to_string()),
-            run_cmd: "./a.out".to_string(),
-            is_compiled: true,
-        },
-        "javascript" => Language {
-            name: "javascript".to_string(),
-            source_file: "main.js".to_string(),
-            compile_cmd: None,
-            run_cmd: "/usr/bin/node main.js".to_string(),
-            is_compiled: false,
-        },
-        "java" => Language {
-            name: "java".to_string(),
-            source_file: "Main.java".to_string(),
-            compile_cmd: Some("/usr/bin/javac Main.java".to_string()),
-            run_cmd: "/usr/bin/java Main".to_string(),
-            is_compiled: false,
-        },
-        "sql" => Language {
-            name: "sql".to_string(),
-            source_file: "main.sql".to_string(),
-            compile_cmd: None,
-            run_cmd: "sqlite3".to_string(),
-            is_compiled: false,
-        },
-        _ => return Err(StatusCode::BAD_REQUEST),
-    };
+    let language = Language::from_name(&payload.language)
+        .ok_or_else(|| StatusCode::BAD_REQUEST.into_response())?;
 
+    let limits = &state.config.limits;
     let settings = ExecutionSettings {
-        cpu_time_limit: payload.time_limit.unwrap_or(2.0),
-        memory_limit: payload.memory_limit.unwrap_or(128_000),
-        stack_limit: payload.stack_limit.unwrap_or(64_000),
+        cpu_time_limit: payload.time_limit.unwrap_or(limits.time_limit),
+        memory_limit: payload.memory_limit.unwrap_or(limits.memory_limit),
+        stack_limit: payload.stack_limit.unwrap_or(limits.stack_limit),
         ..Default::default()
     };
 
@@ -98,32 +430,174 @@ async fn handle_create(
     //     .unwrap()
     //     .as_micros();
     // println!("job prepared {}", exact_current_time);
-    let job = Job::new(payload.code, language)
+    let mut job = Job::new(payload.code, language)
         .with_stdin(payload.input)
         .with_expected_output(payload.expected)
         .set_limits(
             settings.cpu_time_limit,
             settings.memory_limit,
             settings.stack_limit,
-            60,
+            limits.max_processes,
         );
 
-    let job_id = create_job(&redis, job)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if payload.benchmark.unwrap_or(false) {
+        if payload.number_of_runs.is_some_and(|runs| runs > limits.max_runs) {
+            return Err(StatusCode::BAD_REQUEST.into_response());
+        }
+        job = job.with_benchmark(payload.number_of_runs);
+        // The default may be above a lower configured cap
+        job.number_of_runs = job.number_of_runs.min(limits.max_runs);
+    }
+    let tenant = state.tenants.resolve(api_key);
+    if let Some(priority) = payload.priority {
+        job = job.with_priority(priority.at_most(state.tenants.max_priority(tenant)));
+    }
+    if let Some(deadline) = payload.deadline {
+        // Also catches deadlines given in seconds, which read as 1970
+        if deadline <= unix_millis() {
+            return Err(StatusCode::BAD_REQUEST.into_response());
+        }
+        job = job.with_deadline(deadline);
+    }
+    job = job.with_tenant(tenant);
+
+    job.trace_context = telemetry::current_context();
+
+    let language = job.language.name.clone();
+    if state.config.cache.results && !job.benchmark {
+        if let Some(response) = reuse_result(&state, &mut job).await? {
+            metrics().jobs_submitted.with_label_values(&[&language]).inc();
+            return Ok(Json(response));
+        }
+    }
+
+    let fingerprint = job.fingerprint.clone();
+    let created = create_job(state.backend.as_ref(), job)
+        .instrument(tracing::info_span!("enqueue"))
+        .await;
+    let job_id = match created {
+        Ok(job_id) => job_id,
+        Err(e) => {
+            tracing::error!("Error queueing job: {}", e);
+            // Identical submissions that joined this run would wait on a
+            // job that never runs
+            if let Some(fingerprint) = fingerprint {
+                release_claim(&state, &fingerprint).await;
+            }
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+        }
+    };
+    metrics().jobs_submitted.with_label_values(&[&language]).inc();
 
     Ok(Json(json!({ "status": "created", "id": job_id })))
 }
 
+/// Checks the result cache for a job identical to `job`. Answers a hit with
+/// the cached verdict, and parks the job behind an identical one that is
+/// running. Returns `None` when the job should be queued, with its
+/// fingerprint set if it is the run others may wait on.
+async fn reuse_result(
+    state: &AppState,
+    job: &mut Job,
+) -> Result<Option<serde_json::Value>, Response> {
+    let key = job.id.to_string();
+    let fingerprint = job.content_hash();
+    job.fingerprint = Some(fingerprint.clone());
+    let store_error = |e| {
+        tracing::error!("Error storing job {}: {}", key, e);
+        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    };
+
+    // Stored before claiming, so it exists by the time a run it waits on settles
+    job.timings.queued_at = Some(unix_millis());
+    state.backend.store_job(&key, job, None).await.map_err(store_error)?;
+
+    let claim = match state.backend.claim(&fingerprint, &key).await {
+        Ok(claim) => claim,
+        // The cache is an optimization; run the job on its own instead
+        Err(e) => {
+            tracing::error!("Result cache lookup failed: {}", e);
+            job.fingerprint = None;
+            return Ok(None);
+        }
+    };
+
+    match claim {
+        Claim::Hit(result) => {
+            metrics().result_cache.with_label_values(&["hit"]).inc();
+            job.copy_result(&result);
+            let now = unix_millis();
+            job.started_at = Some(now / 1000);
+            job.finished_at = Some(now / 1000);
+            job.timings.finished_at = Some(now);
+            state
+                .backend
+                .store_job(&key, job, state.config.retention.settings().result_ttl)
+                .await
+                .map_err(store_error)?;
+            if let Some(archive) = &state.archive {
+                if let Err(e) = archive.archive_job(job).await {
+                    tracing::error!("Error archiving job {}: {}", key, e);
+                }
+            }
+            metrics().record_verdict(&job.language.name, &job.status);
+            Ok(Some(json!({
+                "status": "created",
+                "id": key,
+                "cached": true,
+                "verdict": {
+                    "id": job.status.id(),
+                    "description": format!("{}", job.status),
+                },
+            })))
+        }
+        Claim::Waiting => {
+            metrics().result_cache.with_label_values(&["coalesced"]).inc();
+            Ok(Some(json!({ "status": "created", "id": key, "coalesced": true })))
+        }
+        Claim::Leader => {
+            metrics().result_cache.with_label_values(&["miss"]).inc();
+            Ok(None)
+        }
+    }
+}
+
+/// Gives up the run claimed for `fingerprint`, queueing the jobs that joined
+/// it to run on their own.
+async fn release_claim(state: &AppState, fingerprint: &str) {
+    let ttl = state.config.result_cache_ttl();
+    match state.backend.settle(fingerprint, None, ttl).await {
+        Ok(waiting) => run_waiters(state.backend.as_ref(), waiting).await,
+        // The claim expires and the sweeper picks its waiters up
+        Err(e) => tracing::error!("Error releasing result cache claim: {}", e),
+    }
+}
+
 async fn handle_check(
-    State(redis): State<Arc<RedisClient>>,
+    State(state): State<Arc<AppState>>,
     Path(job_id): Path<String>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    let job = check_job(&redis, &job_id)
-        .await
-        .map_err(|_| StatusCode::NOT_FOUND)?;
+    let stored = state.backend.get_job(&job_id).await.map_err(|e| {
+        tracing::error!("Error loading job {}: {}", job_id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let job = match stored {
+        Some(job) => job,
+        // Results that expired from the store are still served from the archive
+        None => match &state.archive {
+            Some(archive) => archive
+                .get_job(&job_id)
+                .await
+                .map_err(|e| {
+                    tracing::error!("Error reading job {} from archive: {}", job_id, e);
+                    StatusCode::INTERNAL_SERVER_ERROR
+                })?
+                .ok_or(StatusCode::NOT_FOUND)?,
+            None => return Err(StatusCode::NOT_FOUND),
+        },
+    };
 
-    let send_output = &json!({
+    let mut send_output = json!({
         "started_at": job.started_at.unwrap_or(0),
         "finished_at": job.finished_at.unwrap_or(0),
         "stdout": job.output.stdout.unwrap_or("".to_string()),
@@ -133,14 +607,68 @@ async fn handle_check(
         "token": job.id,
         "compile_output": job.output.compile_output.unwrap_or("".to_string()),
         "message": job.output.message.unwrap_or("".to_string()),
+        "cached": job.cached,
         "status": {
             "id": job.status.id(),
             "description": format!("{}",job.status),
         },
     });
 
+    send_output["timings"] = json!(job.timings);
 
-    Ok(Json(json!(send_output)))
+    if job.benchmark {
+        send_output["runs"] = json!(job.output.runs);
+        send_output["benchmark"] = json!(job.output.benchmark);
+    }
+
+    Ok(Json(send_output))
+}
+
+async fn handle_purge(
+    State(state): State<Arc<AppState>>,
+    Path(job_id): Path<String>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let mut found = false;
+
+    let stored = state.backend.get_job(&job_id).await.map_err(|e| {
+        tracing::error!("Error loading job {} to purge: {}", job_id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    if let Some(mut job) = stored {
+        // A worker would write the source and output back when it finishes
+        if !job.is_finished() {
+            return Err(StatusCode::CONFLICT);
+        }
+        job.purge();
+        if let Some(fingerprint) = &job.fingerprint {
+            // The cached copy holds the same source and output
+            state.backend.forget(fingerprint).await.map_err(|e| {
+                tracing::error!("Error purging cached result of job {}: {}", job_id, e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+        }
+        state
+            .backend
+            .store_job(&job_id, &job, state.config.retention.settings().result_ttl)
+            .await
+            .map_err(|e| {
+                tracing::error!("Error purging job {}: {}", job_id, e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+        found = true;
+    }
+
+    if let Some(archive) = &state.archive {
+        found |= archive.purge_job(&job_id).await.map_err(|e| {
+            tracing::error!("Error purging job {} from archive: {}", job_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    }
+
+    if !found {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(Json(json!({ "status": "purged", "id": job_id })))
 }
 
 async fn handle_debug(
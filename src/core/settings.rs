@@ -1,6 +1,8 @@
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ExecutionSettings {
     pub cpu_time_limit: f64,
     pub wall_time_limit: f64,
@@ -23,4 +25,24 @@ impl Default for ExecutionSettings {
             enable_network: false,
         }
     }
+}
+
+/// How long finished jobs are kept around.
+#[derive(Debug, Clone)]
+pub struct RetentionSettings {
+    /// TTL of finished jobs in the store. `None` keeps them until purged.
+    pub result_ttl: Option<Duration>,
+    /// How long finished jobs stay in the archive. `None` keeps them forever.
+    pub archive_retention: Option<Duration>,
+    pub sweep_interval: Duration,
+}
+
+impl Default for RetentionSettings {
+    fn default() -> Self {
+        Self {
+            result_ttl: Some(Duration::from_secs(24 * 60 * 60)),
+            archive_retention: None,
+            sweep_interval: Duration::from_secs(60),
+        }
+    }
 }
\ No newline at end of file
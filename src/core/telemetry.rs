@@ -0,0 +1,202 @@
+//! Logging and tracing setup. Spans are logged to stdout and, when an OTLP
+//! endpoint is configured, exported to an OpenTelemetry collector.
+//!
+//! The trace context of the request that submitted a job is stored on the
+//! job, so the worker's spans join the same trace even in another process.
+
+use super::config::{OtlpProtocol, TelemetryConfig};
+use opentelemetry::{
+    global,
+    trace::{Span, Tracer, TracerProvider},
+    Context,
+};
+use opentelemetry_otlp::{Protocol, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::SdkTracerProvider, Resource};
+use std::{collections::HashMap, time::SystemTime};
+use tracing::level_filters::LevelFilter;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+/// Installs the global subscriber. Returns the tracer provider when export
+/// is enabled; shut it down before exiting to flush buffered spans.
+pub fn init(config: &TelemetryConfig) -> Result<Option<SdkTracerProvider>, String> {
+    let subscriber = tracing_subscriber::registry()
+        .with(LevelFilter::INFO)
+        .with(tracing_subscriber::fmt::layer());
+
+    let Some(endpoint) = &config.otlp_endpoint else {
+        subscriber.init();
+        return Ok(None);
+    };
+
+    let protocol = match config.protocol {
+        OtlpProtocol::Protobuf => Protocol::HttpBinary,
+        OtlpProtocol::Json => Protocol::HttpJson,
+    };
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_protocol(protocol)
+        .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+        .build()
+        .map_err(|e| format!("Failed to create OTLP exporter: {}", e))?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            Resource::builder()
+                .with_service_name(config.service_name.clone())
+                .build(),
+        )
+        .build();
+
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    global::set_tracer_provider(provider.clone());
+    subscriber
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("flash")))
+        .init();
+    Ok(Some(provider))
+}
+
+/// W3C trace context of the current span, to store on a job. Empty when
+/// export is disabled.
+pub fn current_context() -> HashMap<String, String> {
+    let mut carrier = HashMap::new();
+    let cx = tracing::Span::current().context();
+    global::get_text_map_propagator(|propagator| propagator.inject_context(&cx, &mut carrier));
+    carrier
+}
+
+/// Makes `span` a child of the trace stored on a job.
+pub fn resume(span: &tracing::Span, carrier: &HashMap<String, String>) {
+    let parent = global::get_text_map_propagator(|propagator| propagator.extract(carrier));
+    let _ = span.set_parent(parent);
+}
+
+/// Records a span that has already ended, such as the time a job spent in
+/// the queue, as a child of the trace stored on the job.
+pub fn record_span(
+    name: &'static str,
+    carrier: &HashMap<String, String>,
+    start: SystemTime,
+    end: SystemTime,
+) {
+    let parent: Context = global::get_text_map_propagator(|propagator| propagator.extract(carrier));
+    let tracer = global::tracer("flash");
+    let mut span = tracer
+        .span_builder(name)
+        .with_start_time(start)
+        .start_with_context(&tracer, &parent);
+    span.end_with_timestamp(end);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{Job, Language};
+    use crate::core::config::OtlpProtocol;
+    use opentelemetry_sdk::trace::InMemorySpanExporter;
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        sync::{mpsc, Mutex},
+        thread,
+        time::Duration,
+    };
+
+    /// Held by tests that set the global tracer provider.
+    static GLOBAL_PROVIDER: Mutex<()> = Mutex::new(());
+
+    /// Answers one OTLP/HTTP request on a local port with success, and sends
+    /// its request line and body.
+    fn collector() -> (String, mpsc::Receiver<(String, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut length = 0;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = header.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            reader
+                .get_mut()
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: 2\r\n\r\n{}")
+                .unwrap();
+            let _ = sender.send((request_line.trim().to_string(), String::from_utf8_lossy(&body).into_owned()));
+        });
+        (endpoint, receiver)
+    }
+
+    #[test]
+    fn exports_spans_to_the_configured_collector() {
+        let _global = GLOBAL_PROVIDER.lock().unwrap_or_else(|e| e.into_inner());
+        let (endpoint, requests) = collector();
+        let config = TelemetryConfig {
+            otlp_endpoint: Some(format!("{}/", endpoint)),
+            protocol: OtlpProtocol::Json,
+            service_name: "flash-test".to_string(),
+        };
+
+        let provider = init(&config).unwrap().expect("export is configured");
+        tracing::info_span!("exported").in_scope(|| {});
+        provider.force_flush().unwrap();
+
+        let (request_line, body) = requests.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert!(request_line.starts_with("POST /v1/traces "), "{}", request_line);
+        assert!(body.contains("\"exported\""), "{}", body);
+        assert!(body.contains("flash-test"), "{}", body);
+        provider.shutdown().unwrap();
+    }
+
+    #[test]
+    fn worker_spans_join_the_submitting_trace() {
+        let _global = GLOBAL_PROVIDER.lock().unwrap_or_else(|e| e.into_inner());
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        global::set_tracer_provider(provider.clone());
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("flash")));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let mut job = Job::new("print(1)".to_string(), Language::from_name("python").unwrap());
+            tracing::info_span!("create").in_scope(|| {
+                job.trace_context = current_context();
+            });
+            assert!(job.trace_context.contains_key("traceparent"));
+
+            // As the worker sees it, after a trip through the store
+            let stored: Job = serde_json::from_slice(&serde_json::to_vec(&job).unwrap()).unwrap();
+            let now = SystemTime::now();
+            record_span("queue_wait", &stored.trace_context, now - Duration::from_millis(5), now);
+            let span = tracing::info_span!("judge");
+            resume(&span, &stored.trace_context);
+            span.in_scope(|| tracing::info_span!("compile").in_scope(|| {}));
+        });
+        provider.force_flush().unwrap();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let span = |name: &str| spans.iter().find(|span| span.name == name).unwrap();
+        let create = span("create");
+        for name in ["queue_wait", "judge"] {
+            assert_eq!(span(name).span_context.trace_id(), create.span_context.trace_id());
+            assert_eq!(span(name).parent_span_id, create.span_context.span_id());
+        }
+        assert_eq!(span("compile").parent_span_id, span("judge").span_context.span_id());
+    }
+}
@@ -0,0 +1,90 @@
+//! API clients grouped into tenants for fair scheduling.
+//!
+//! Each tenant has its own sub-queues, and workers share their slots
+//! between tenants by weight. Requests whose `X-Api-Key` isn't listed under
+//! any tenant belong to the default tenant.
+
+use super::{config::TenantConfig, queue_name, Language, Priority};
+use std::collections::{BTreeMap, HashMap};
+
+pub const DEFAULT_TENANT: &str = "default";
+
+#[derive(Debug, Clone)]
+pub struct Tenants {
+    /// Settings by tenant name. Always has the default tenant.
+    config: BTreeMap<String, TenantConfig>,
+    by_key: HashMap<String, String>,
+}
+
+impl Tenants {
+    pub fn new(config: &BTreeMap<String, TenantConfig>) -> Self {
+        let mut config = config.clone();
+        config.entry(DEFAULT_TENANT.to_string()).or_default();
+        let by_key = config
+            .iter()
+            .flat_map(|(name, tenant)| tenant.keys.iter().map(move |key| (key.clone(), name.clone())))
+            .collect();
+        Self { config, by_key }
+    }
+
+    /// The tenant a request made with `api_key` belongs to.
+    pub fn resolve(&self, api_key: Option<&str>) -> &str {
+        api_key
+            .and_then(|key| self.by_key.get(key))
+            .map_or(DEFAULT_TENANT, String::as_str)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.config.keys().map(String::as_str)
+    }
+
+    pub fn weight(&self, tenant: &str) -> u32 {
+        self.config.get(tenant).map_or(1, |tenant| tenant.weight)
+    }
+
+    /// Jobs of `tenant` allowed to run at once across the fleet, as
+    /// counted from heartbeats.
+    pub fn max_concurrency(&self, tenant: &str) -> Option<usize> {
+        self.config
+            .get(tenant)
+            .map(|tenant| tenant.max_concurrency)
+            .filter(|max| *max > 0)
+    }
+
+    /// Highest priority `tenant`'s clients may use.
+    pub fn max_priority(&self, tenant: &str) -> Priority {
+        self.config
+            .get(tenant)
+            .map_or(Priority::Normal, |tenant| tenant.max_priority)
+    }
+
+    /// Every queue of every tenant, highest priority first, including the
+    /// ones from before jobs were routed by language.
+    pub fn queues(&self) -> Vec<String> {
+        let languages: Vec<&str> = Language::NAMES.into_iter().chain([""]).collect();
+        Priority::ALL
+            .iter()
+            .flat_map(|priority| self.names().map(move |tenant| (priority, tenant)))
+            .flat_map(|(priority, tenant)| {
+                languages
+                    .iter()
+                    .map(move |language| queue_name(*priority, tenant, language))
+            })
+            .collect()
+    }
+
+    /// The queues every tenant's jobs went to before they were routed by
+    /// language, highest priority first.
+    pub fn legacy_queues(&self) -> Vec<String> {
+        Priority::ALL
+            .iter()
+            .flat_map(|priority| self.names().map(move |tenant| queue_name(*priority, tenant, "")))
+            .collect()
+    }
+}
+
+impl Default for Tenants {
+    fn default() -> Self {
+        Self::new(&BTreeMap::new())
+    }
+}
@@ -0,0 +1,124 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use uuid::Uuid;
+
+/// A running worker process, as recorded in the worker registry.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WorkerInfo {
+    pub id: String,
+    pub host: String,
+    pub pid: u32,
+    pub concurrency: usize,
+    /// Languages the worker runs. Empty means all of them.
+    pub languages: Vec<String>,
+    /// Slots that only run one language, by language.
+    pub reserved_slots: BTreeMap<String, usize>,
+    pub version: String,
+    pub started_at: i64,
+    pub last_heartbeat: i64,
+    /// Jobs the worker was running at its last heartbeat.
+    pub jobs: Vec<ActiveJob>,
+    /// Jobs the worker has finished since it started.
+    pub jobs_done: u64,
+    /// Latest canary result of each language the worker runs. Languages
+    /// without a result yet are taken as healthy.
+    pub canaries: BTreeMap<String, CanaryResult>,
+}
+
+/// Outcome of running a language's built-in self-test programs.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CanaryResult {
+    pub healthy: bool,
+    pub checked_at: i64,
+    /// What went wrong, when unhealthy.
+    pub detail: Option<String>,
+}
+
+impl CanaryResult {
+    pub fn new(failure: Option<String>) -> Self {
+        Self {
+            healthy: failure.is_none(),
+            checked_at: unix_now(),
+            detail: failure,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ActiveJob {
+    pub id: u64,
+    pub language: String,
+    pub tenant: String,
+    pub box_id: u64,
+    pub started_at: i64,
+}
+
+impl WorkerInfo {
+    /// Describes this process. The id is unique per process start.
+    pub fn new(
+        concurrency: usize,
+        languages: Vec<String>,
+        reserved_slots: BTreeMap<String, usize>,
+    ) -> Self {
+        let host = hostname();
+        let pid = std::process::id();
+        let now = unix_now();
+        Self {
+            id: format!("{}-{}-{}", host, pid, &Uuid::new_v4().to_simple().to_string()[..8]),
+            host,
+            pid,
+            concurrency,
+            languages,
+            reserved_slots,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            started_at: now,
+            last_heartbeat: now,
+            jobs: Vec::new(),
+            jobs_done: 0,
+            canaries: BTreeMap::new(),
+        }
+    }
+
+    /// Records a heartbeat now, with the jobs currently running and the
+    /// number finished so far.
+    pub fn beat(&mut self, jobs: Vec<ActiveJob>, jobs_done: u64) {
+        self.last_heartbeat = unix_now();
+        self.jobs = jobs;
+        self.jobs_done = jobs_done;
+    }
+
+    /// Whether the worker takes jobs in `language`: it runs the language and
+    /// the language's last canary passed.
+    pub fn serves(&self, language: &str) -> bool {
+        (self.languages.is_empty() || self.languages.iter().any(|l| l == language))
+            && self.canaries.get(language).is_none_or(|canary| canary.healthy)
+    }
+
+    /// Whether the worker has heartbeated within the last `ttl`.
+    pub fn is_alive(&self, ttl: Duration) -> bool {
+        self.last_heartbeat >= unix_now() - ttl.as_secs() as i64
+    }
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+fn hostname() -> String {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|name| name.trim().to_string())
+        .ok()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
@@ -1,30 +1,437 @@
-use num_cpus;
-
-use flash::client::redis::RedisClient;
-use flash::core::server::server;
-use flash::worker::Worker;
-use tokio;
-
-#[tokio::main(flavor = "multi_thread", worker_threads = 4)]
-async fn main() {
-    tracing_subscriber::fmt::init();
-    let cpu_count: usize = num_cpus::get();
-    println!("Number of CPU cores: {}", cpu_count);
-    // Initialize Redis client
-    let redis_client = RedisClient::new("redis://127.0.0.1/").expect("Failed to connect to Redis");
-
-    // Start the worker
-    let worker_redis = redis_client.clone();
-    tokio::spawn(async move {
-        let worker = Worker::new(worker_redis);
-        worker.start(cpu_count * 2).await;
+use clap::{Args, Parser, Subcommand};
+use flash::client::{archive::JobArchive, memory::MemoryClient, redis::RedisClient, Backend};
+use flash::core::admission::Admission;
+use flash::core::server::{metrics_server, server, AppState};
+use flash::core::{telemetry, tenant::Tenants};
+use flash::core::{
+    BackendKind, Config, ConfigOverrides, Job, JobStatus, Language, PreflightMode, Summary,
+    WorkerOverrides,
+};
+use flash::vendors::{artifacts::ArtifactCache, isolate::IsolateExecutor, preflight};
+use flash::worker::{
+    cleanup_box, lock_boxes,
+    sweeper::{run_archiver, run_sweeper},
+    Worker,
+};
+use std::{fs, path::PathBuf, sync::Arc};
+use tokio::sync::watch;
+
+/// Configuration file read when `--config` isn't given, if it exists.
+const DEFAULT_CONFIG_PATH: &str = "flash.toml";
+
+#[derive(Parser)]
+#[command(version, about = "High-performance code execution and isolation system")]
+struct Cli {
+    /// TOML configuration file
+    #[arg(long, env = "FLASH_CONFIG", global = true)]
+    config: Option<PathBuf>,
+
+    #[command(flatten)]
+    overrides: ConfigOverrides,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the HTTP API only
+    Server,
+    /// Run judging workers only
+    Worker(WorkerOverrides),
+    /// Run the HTTP API and workers in one process (the default)
+    All(WorkerOverrides),
+    /// Judge a single source file locally, without a server or job store
+    Run(RunArgs),
+}
+
+#[derive(Args)]
+struct RunArgs {
+    /// Source file to compile and run
+    source: PathBuf,
+
+    /// Language of the source file
+    #[arg(long)]
+    lang: String,
+
+    /// File passed to the program on stdin
+    #[arg(long)]
+    stdin: Option<PathBuf>,
+
+    /// File the program's output is compared against
+    #[arg(long)]
+    expect: Option<PathBuf>,
+
+    /// CPU time limit in seconds
+    #[arg(long)]
+    time: Option<f64>,
+
+    /// Memory limit in KB
+    #[arg(long)]
+    memory: Option<u64>,
+
+    /// Run the program this many times and report timing statistics
+    #[arg(long)]
+    runs: Option<u8>,
+
+    /// Isolate box to run in, instead of isolate.local_box
+    #[arg(long = "box")]
+    box_id: Option<u64>,
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let command = cli.command.unwrap_or(Command::All(WorkerOverrides::default()));
+    let mut overrides = cli.overrides;
+    if let Command::Worker(worker) | Command::All(worker) = &command {
+        overrides.worker = worker.clone();
+    }
+
+    let config_path = cli
+        .config
+        .or_else(|| Some(PathBuf::from(DEFAULT_CONFIG_PATH)).filter(|p| p.exists()));
+    let config = match Config::load(config_path.as_deref(), &overrides) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+
+    let tracer_provider = match telemetry::init(&config.telemetry) {
+        Ok(provider) => provider,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(config.runtime.worker_threads)
+        .enable_all()
+        .build()
+        .expect("Failed to build tokio runtime")
+        .block_on(run(command, config));
+
+    // Flush spans still buffered for export
+    if let Some(provider) = tracer_provider {
+        if let Err(e) = provider.shutdown() {
+            eprintln!("Failed to flush traces: {}", e);
+        }
+    }
+}
+
+/// The parts a long-running process plays.
+enum Role {
+    Server,
+    Worker,
+    All,
+}
+
+async fn run(command: Command, config: Config) {
+    let role = match command {
+        Command::Run(args) => {
+            let accepted = run_local(args, &config).await;
+            std::process::exit(if accepted { 0 } else { 1 });
+        }
+        Command::Server => Role::Server,
+        Command::Worker(_) => Role::Worker,
+        Command::All(_) => Role::All,
+    };
+    serve(role, config).await;
+}
+
+/// Runs `role` until a shutdown signal, then drains.
+async fn serve(role: Role, config: Config) {
+    let config = Arc::new(config);
+    let backend = connect_backend(&config).await;
+    let drain = shutdown_signal();
+    let shutdown = drain.subscribe();
+
+    match role {
+        Role::Server => {
+            let archive = open_archive(&config);
+            // Workers elsewhere can't reach this archive, so collect their results
+            tokio::spawn(run_archiver(
+                backend.clone(),
+                archive.clone(),
+                config.retention.settings().sweep_interval,
+            ));
+            run_server(config, backend, archive, drain, shutdown).await;
+        }
+        Role::Worker => {
+            if let Some(bind) = config.worker.metrics_bind.clone() {
+                tokio::spawn(serve_metrics(bind, shutdown.clone()));
+            }
+            run_worker(config, backend, None, shutdown).await;
+        }
+        Role::All => {
+            let archive = open_archive(&config);
+            let worker = tokio::spawn(run_worker(
+                config.clone(),
+                backend.clone(),
+                Some(archive.clone()),
+                shutdown.clone(),
+            ));
+            run_server(config, backend, archive, drain, shutdown).await;
+            let _ = worker.await;
+        }
+    }
+    println!("Shut down");
+}
+
+/// Runs one job in a local box and prints the result. Returns whether it
+/// was accepted.
+async fn run_local(args: RunArgs, config: &Config) -> bool {
+    let read = |path: &PathBuf| {
+        fs::read_to_string(path).unwrap_or_else(|e| {
+            eprintln!("Failed to read {}: {}", path.display(), e);
+            std::process::exit(2);
+        })
+    };
+
+    let Some(language) = Language::from_name(&args.lang) else {
+        eprintln!("Unsupported language: {}", args.lang);
+        std::process::exit(2);
+    };
+
+    let limits = &config.limits;
+    let mut job = Job::new(read(&args.source), language)
+        .with_stdin(args.stdin.as_ref().map(read).unwrap_or_default())
+        .with_expected_output(args.expect.as_ref().map(read).unwrap_or_default())
+        .set_limits(
+            args.time.unwrap_or(limits.time_limit),
+            args.memory.unwrap_or(limits.memory_limit),
+            limits.stack_limit,
+            limits.max_processes,
+        );
+    if args.runs.is_some() {
+        job = job.with_benchmark(args.runs);
+    }
+
+    let box_id = args.box_id.unwrap_or(config.isolate.local_box);
+    let _lock = match lock_boxes(&config.isolate.box_root, box_id..box_id + 1).await {
+        Ok(lock) => lock,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    let executor = IsolateExecutor::standalone()
+        .with_box_root(config.isolate.box_root.clone())
+        .with_box_id(box_id);
+    let result = executor.run_job(&mut job).await;
+    cleanup_box(box_id).await;
+    if let Err(e) = result {
+        eprintln!("Failed to run job: {}", e);
+        return false;
+    }
+
+    let output = &job.output;
+    println!("Verdict:   {}", job.status);
+    if let Some(time) = output.time {
+        println!("Time:      {:.3}s", time);
+    }
+    if let Some(memory) = output.memory {
+        println!("Memory:    {} KB", memory);
+    }
+    if let Some(exit_code) = output.exit_code {
+        println!("Exit code: {}", exit_code);
+    }
+    if let Some(message) = &output.message {
+        println!("Message:   {}", message);
+    }
+    if let Some(stats) = &output.benchmark {
+        let print = |name: &str, s: &Summary| {
+            println!(
+                "{:<10} min {:.3} median {:.3} mean {:.3} stddev {:.3}",
+                name, s.min, s.median, s.mean, s.stddev
+            )
+        };
+        println!("Runs:      {}", output.runs.len());
+        print("CPU time", &stats.cpu_time);
+        print("Wall time", &stats.wall_time);
+        print("Memory", &stats.memory);
+    }
+    for (name, text) in [
+        ("Compile output", &output.compile_output),
+        ("Stdout", &output.stdout),
+        ("Stderr", &output.stderr),
+    ] {
+        if let Some(text) = text.as_deref().filter(|t| !t.is_empty()) {
+            println!("--- {} ---\n{}", name, text.trim_end());
+        }
+    }
+
+    job.status == JobStatus::Accepted
+}
+
+async fn connect_backend(config: &Config) -> Arc<dyn Backend> {
+    match config.store.backend {
+        BackendKind::Memory => {
+            println!("Using in-memory job backend");
+            Arc::new(MemoryClient::new())
+        }
+        BackendKind::Redis => {
+            let redis = RedisClient::new(&config.store.redis_url, config.store.redis.clone())
+                .expect("Failed to connect to Redis");
+            if let Err(e) = redis.test_connection().await {
+                eprintln!("Failed to connect to Redis: {}", e);
+                std::process::exit(1);
+            }
+            // Rewrite jobs queued by older versions as key-only entries
+            if let Err(e) = redis.migrate_queue("jobs").await {
+                eprintln!("Failed to migrate job queue: {}", e);
+            }
+            Arc::new(redis)
+        }
+    }
+}
+
+fn open_archive(config: &Config) -> JobArchive {
+    JobArchive::open(&config.store.archive_path).expect("Failed to open job archive")
+}
+
+async fn run_server(
+    config: Arc<Config>,
+    backend: Arc<dyn Backend>,
+    archive: JobArchive,
+    drain: Arc<watch::Sender<bool>>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    tokio::spawn(run_sweeper(
+        backend.clone(),
+        Some(archive.clone()),
+        config.retention.settings(),
+    ));
+
+    let bind = config.server.bind.clone();
+    let tenants = Tenants::new(&config.tenants);
+    let admission = Admission::new(config.admission.clone(), tenants.queues(), config.heartbeat_ttl());
+    let app = server(AppState {
+        backend,
+        archive: Some(archive),
+        admission: Arc::new(admission),
+        tenants: Arc::new(tenants),
+        config,
+        drain,
     });
 
-    // Start the server
-    let app = server(redis_client);
+    let listener = tokio::net::TcpListener::bind(&bind).await.unwrap();
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3001").await.unwrap();
+    println!("Server running on http://{}", bind);
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async move {
+            let _ = shutdown.wait_for(|stop| *stop).await;
+        })
+        .await
+        .unwrap();
+}
+
+async fn serve_metrics(bind: String, mut shutdown: watch::Receiver<bool>) {
+    let listener = match tokio::net::TcpListener::bind(&bind).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Failed to bind metrics listener on {}: {}", bind, e);
+            return;
+        }
+    };
+
+    println!("Metrics on http://{}/metrics", bind);
+    let result = axum::serve(listener, metrics_server())
+        .with_graceful_shutdown(async move {
+            let _ = shutdown.wait_for(|stop| *stop).await;
+        })
+        .await;
+    if let Err(e) = result {
+        eprintln!("Metrics listener failed: {}", e);
+    }
+}
+
+async fn run_worker(
+    config: Arc<Config>,
+    backend: Arc<dyn Backend>,
+    archive: Option<JobArchive>,
+    shutdown: watch::Receiver<bool>,
+) {
+    check_host(config.isolate.preflight).await;
+
+    let concurrency = config.concurrency();
+    println!("Number of CPU cores: {}", num_cpus::get());
+    println!("Starting {} workers", concurrency);
+
+    let mut worker = Worker::new(backend)
+        .with_result_ttl(config.retention.settings().result_ttl)
+        .with_box_root(config.isolate.box_root.clone())
+        .with_languages(config.worker.languages.clone())
+        .with_reserved_slots(config.worker.reserved_slots.clone())
+        .with_tenants(Tenants::new(&config.tenants))
+        .with_heartbeat(config.heartbeat_interval(), config.heartbeat_ttl())
+        .with_drain_timeout(config.drain_timeout())
+        .with_starvation_timeout(config.starvation_timeout())
+        .with_canary_interval(config.canary_interval())
+        .with_result_cache_ttl(config.result_cache_ttl())
+        .with_first_box(config.worker.first_box);
+    if let Some(archive) = archive {
+        worker = worker.with_archive(archive);
+    }
+    if config.cache.artifacts {
+        let max_bytes = config.cache.artifact_max_mb * 1024 * 1024;
+        worker = worker.with_artifact_cache(ArtifactCache::new(&config.cache.artifact_dir, max_bytes));
+    }
+    if let Err(e) = worker.start(concurrency, shutdown).await {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Runs the host preflight checks, logging problems and exiting when a check
+/// fails under `enforce`.
+async fn check_host(mode: PreflightMode) {
+    if mode == PreflightMode::Off {
+        return;
+    }
 
-    println!("Server running on http://0.0.0.0:3001");
-    axum::serve(listener, app).await.unwrap();
+    let report = tokio::task::spawn_blocking(preflight::run)
+        .await
+        .expect("Preflight check panicked");
+    for check in report.problems() {
+        eprintln!(
+            "Preflight {:?}: {}: {}",
+            check.status,
+            check.name,
+            check.detail.as_deref().unwrap_or_default()
+        );
+        if let Some(fix) = &check.fix {
+            eprintln!("  fix with: {}", fix);
+        }
+    }
+
+    if report.failed() {
+        if mode == PreflightMode::Enforce {
+            eprintln!("Preflight checks failed, refusing to start (set isolate.preflight = \"warn\" to override)");
+            std::process::exit(1);
+        }
+        eprintln!("WARNING: preflight checks failed, job limits or isolation may not hold");
+    } else {
+        println!("Preflight checks passed");
+    }
+}
+
+/// Turns true on Ctrl-C or SIGTERM, or when the server is asked to drain.
+fn shutdown_signal() -> Arc<watch::Sender<bool>> {
+    let tx = Arc::new(watch::Sender::new(false));
+    let drain = Arc::clone(&tx);
+    tokio::spawn(async move {
+        let ctrl_c = tokio::signal::ctrl_c();
+        let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to install SIGTERM handler");
+        tokio::select! {
+            _ = ctrl_c => {},
+            _ = terminate.recv() => {},
+        }
+        println!("Shutdown requested, finishing in-flight work");
+        tx.send_replace(true);
+    });
+    drain
 }
@@ -1,8 +1,12 @@
-use crate::{client::redis::RedisClient, core::job::Job};
+use crate::{
+    client::{Backend, JobQueue},
+    core::job::{unix_millis, Job},
+};
 
-/// Creates a new job and stores it in Redis.
-pub async fn create_job(redis: &RedisClient, job: Job) -> Result<String, String> {
+/// Creates a new job and pushes it onto the job queue.
+pub async fn create_job(queue: &dyn JobQueue, mut job: Job) -> Result<String, String> {
     let job_id = job.id.to_string();
+    job.timings.queued_at = Some(unix_millis());
 
 
     // redis
@@ -15,22 +19,29 @@ pub async fn create_job(redis: &RedisClient, job: Job) -> Result<String, String>
     //     .await
     //     .map_err(|e| e.to_string())?;
 
-    redis.create_job(&job_id, "jobs", &job)
+    queue.create_job(&job_id, &job.queue(), &job)
         .await
         .map_err(|e| e.to_string())?;
 
     Ok(job_id)
 }
 
-/// Retrieves a job from Redis by its ID.
-pub async fn check_job(redis: &RedisClient, job_id: &str) -> Result<Job, String> {
-    let data=redis
-        .get_job(job_id)
-        .await
-        .map_err(|e| e.to_string());
-    match data {
-        Ok(Some(job)) => Ok(job),
-        Ok(None) => Err("Job not found".to_string()),
-        Err(e) => Err(e),
+/// Queues jobs that waited on an identical run which won't give them a
+/// result, to run on their own.
+pub async fn run_waiters(backend: &dyn Backend, keys: Vec<String>) {
+    for key in keys {
+        let mut job = match backend.get_job(&key).await {
+            Ok(Some(job)) if !job.is_finished() => job,
+            Ok(_) => continue,
+            Err(e) => {
+                tracing::error!("Error loading waiting job {}: {}", key, e);
+                continue;
+            }
+        };
+        // Without it the job would settle a run it doesn't own
+        job.fingerprint = None;
+        if let Err(e) = backend.create_job(&key, &job.queue(), &job).await {
+            tracing::error!("Error queueing waiting job {}: {}", key, e);
+        }
     }
 }
@@ -0,0 +1,318 @@
+use crate::core::{metrics::metrics, Language};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{fs, sync::OnceCell, task};
+use uuid::Uuid;
+
+/// File in each entry holding the compiler's output. Its modification time
+/// records when the entry was last used.
+const COMPILE_OUTPUT: &str = "compile_output";
+/// Directory in each entry holding the files the compile produced.
+const FILES: &str = "files";
+/// Prefix of entries still being written.
+const STAGING_PREFIX: &str = ".tmp-";
+/// Age at which an entry left half-written by a crashed worker is removed.
+const STALE_STAGING: Duration = Duration::from_secs(60 * 60);
+
+/// Compile results on local disk, shared by every slot of the worker, so a
+/// source compiled before is copied into the box instead of compiled again.
+/// Entries are keyed on the source, the compile command and the compiler
+/// binary. Once the directory grows past `max_bytes` the least recently used
+/// are evicted.
+///
+/// Sizes and last uses are kept in memory, read from the directory once on
+/// first use. Workers sharing the directory each count the entries they
+/// stored or used, and notice the others' evictions when they next use an
+/// entry.
+#[derive(Clone)]
+pub struct ArtifactCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    index: Arc<OnceCell<Mutex<Index>>>,
+}
+
+#[derive(Default)]
+struct Index {
+    entries: HashMap<String, Entry>,
+    /// Sum of the entries' sizes.
+    total: u64,
+}
+
+struct Entry {
+    size: u64,
+    used: SystemTime,
+}
+
+impl Index {
+    fn insert(&mut self, key: &str, size: u64, used: SystemTime) {
+        if let Some(old) = self.entries.insert(key.to_string(), Entry { size, used }) {
+            self.total -= old.size;
+        }
+        self.total += size;
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(old) = self.entries.remove(key) {
+            self.total -= old.size;
+        }
+    }
+}
+
+impl ArtifactCache {
+    pub fn new(dir: impl Into<PathBuf>, max_bytes: u64) -> Self {
+        Self {
+            dir: dir.into(),
+            max_bytes,
+            index: Arc::new(OnceCell::new()),
+        }
+    }
+
+    /// Hex SHA-256 of the source file name, compile command, the identity
+    /// of the compiler it runs and the source, so upgrading a compiler
+    /// leaves its old artifacts unused.
+    pub async fn key(language: &Language, source_code: &str) -> String {
+        let compiler = match language.compile_cmd.as_deref().and_then(|cmd| cmd.split_whitespace().next()) {
+            Some(program) => toolchain(program).await,
+            None => String::new(),
+        };
+        let inputs = (&language.source_file, &language.compile_cmd, compiler, source_code);
+        let bytes = serde_json::to_vec(&inputs).expect("compile inputs serialize");
+        Sha256::digest(bytes)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// Copies the artifacts stored under `key` into `box_dir` and returns
+    /// the compile output, or `None` if there are none.
+    pub async fn restore(&self, key: &str, box_dir: &Path) -> Option<String> {
+        let entry = self.dir.join(key);
+        let output_path = entry.join(COMPILE_OUTPUT);
+        let Ok(compile_output) = fs::read_to_string(&output_path).await else {
+            // Evicted by another worker sharing the directory, if it was known
+            self.index().await.lock().unwrap().remove(key);
+            metrics().artifact_cache.with_label_values(&["miss"]).inc();
+            return None;
+        };
+        if let Err(e) = copy_tree(&entry.join(FILES), box_dir).await {
+            // Evicted while it was being copied; the compile overwrites what got through
+            tracing::warn!("Failed to restore compiled artifacts {}: {:?}", key, e);
+            self.index().await.lock().unwrap().remove(key);
+            metrics().artifact_cache.with_label_values(&["miss"]).inc();
+            return None;
+        }
+        touch(output_path).await;
+
+        let now = SystemTime::now();
+        let known = match self.index().await.lock().unwrap().entries.get_mut(key) {
+            Some(known) => {
+                known.used = now;
+                true
+            }
+            None => false,
+        };
+        if !known {
+            // Stored by another worker sharing the directory
+            if let Ok(size) = tree_size(&entry).await {
+                self.index().await.lock().unwrap().insert(key, size, now);
+            }
+        }
+        metrics().artifact_cache.with_label_values(&["hit"]).inc();
+        Some(compile_output)
+    }
+
+    /// Stores the files in `box_dir`, except the `inputs` to the compile, as
+    /// the artifacts of `key`, then evicts entries if the cache is too big.
+    pub async fn store(&self, key: &str, box_dir: &Path, inputs: &[&str], compile_output: &str) {
+        let entry = self.dir.join(key);
+        // Built aside and renamed into place, so a reader never sees part of it
+        let staging = self.dir.join(format!("{}{}-{}", STAGING_PREFIX, key, Uuid::new_v4()));
+        let written = async {
+            let files = staging.join(FILES);
+            fs::create_dir_all(&files).await?;
+            let mut produced = fs::read_dir(box_dir).await?;
+            while let Some(file) = produced.next_entry().await? {
+                let name = file.file_name();
+                if inputs.iter().any(|input| name == *input) {
+                    continue;
+                }
+                copy_tree(&file.path(), &files.join(name)).await?;
+            }
+            fs::write(staging.join(COMPILE_OUTPUT), compile_output).await?;
+            let size = tree_size(&staging).await?;
+            fs::rename(&staging, &entry).await?;
+            Ok::<_, Error>(size)
+        }
+        .await;
+
+        match written {
+            Ok(size) => self.index().await.lock().unwrap().insert(key, size, SystemTime::now()),
+            Err(e) => {
+                // Another slot may have stored the same source first
+                if !fs::try_exists(&entry).await.unwrap_or(false) {
+                    tracing::warn!("Failed to store compiled artifacts {}: {:?}", key, e);
+                }
+                let _ = fs::remove_dir_all(&staging).await;
+                return;
+            }
+        }
+        self.evict().await;
+    }
+
+    /// Removes the least recently used entries until the cache fits in
+    /// `max_bytes`.
+    async fn evict(&self) {
+        let (victims, total) = {
+            let mut index = self.index().await.lock().unwrap();
+            let mut by_use: Vec<(String, SystemTime)> = index
+                .entries
+                .iter()
+                .map(|(key, entry)| (key.clone(), entry.used))
+                .collect();
+            by_use.sort_by_key(|(_, used)| *used);
+
+            let mut victims = Vec::new();
+            for (key, _) in by_use {
+                if index.total <= self.max_bytes {
+                    break;
+                }
+                index.remove(&key);
+                victims.push(key);
+            }
+            (victims, index.total)
+        };
+
+        for key in victims {
+            let path = self.dir.join(&key);
+            match fs::remove_dir_all(&path).await {
+                Ok(()) => metrics().artifact_cache.with_label_values(&["evicted"]).inc(),
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => tracing::warn!("Failed to evict {}: {:?}", path.display(), e),
+            }
+        }
+        metrics().artifact_cache_bytes.set(total as i64);
+    }
+
+    /// The index, read from the directory the first time.
+    async fn index(&self) -> &Mutex<Index> {
+        self.index
+            .get_or_init(|| async {
+                let index = self.scan().await.unwrap_or_else(|e| {
+                    tracing::warn!("Failed to list compiled artifacts in {}: {:?}", self.dir.display(), e);
+                    Index::default()
+                });
+                metrics().artifact_cache_bytes.set(index.total as i64);
+                Mutex::new(index)
+            })
+            .await
+    }
+
+    /// Every complete entry in the directory with its size and last use.
+    /// Removes staging entries abandoned long ago.
+    async fn scan(&self) -> Result<Index, Error> {
+        let mut index = Index::default();
+        let mut dir = match fs::read_dir(&self.dir).await {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(index),
+            Err(e) => return Err(e),
+        };
+        while let Some(item) = dir.next_entry().await? {
+            let path = item.path();
+            let metadata = item.metadata().await?;
+            let name = item.file_name().to_string_lossy().into_owned();
+            if name.starts_with(STAGING_PREFIX) {
+                let age = metadata.modified()?.elapsed().unwrap_or_default();
+                if age > STALE_STAGING {
+                    let _ = fs::remove_dir_all(&path).await;
+                }
+                continue;
+            }
+            if !metadata.is_dir() {
+                continue;
+            }
+            let used = match fs::metadata(path.join(COMPILE_OUTPUT)).await {
+                Ok(output) => output.modified()?,
+                // Being evicted by another worker sharing the directory
+                Err(_) => continue,
+            };
+            index.insert(&name, tree_size(&path).await?, used);
+        }
+        Ok(index)
+    }
+}
+
+/// Canonical path, modification time and size of the compiler `program`,
+/// which change when it is upgraded. Empty if it can't be read.
+async fn toolchain(program: &str) -> String {
+    let Ok(path) = fs::canonicalize(program).await else {
+        return String::new();
+    };
+    let Ok(metadata) = fs::metadata(&path).await else {
+        return String::new();
+    };
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |modified| modified.as_nanos());
+    format!("{}:{}:{}", path.display(), modified, metadata.len())
+}
+
+/// Copies `from`, a file or a directory with everything under it, to `to`.
+/// Anything but regular files and directories is skipped, so a symlink left
+/// by the compile can't pull in files from outside the box.
+async fn copy_tree(from: &Path, to: &Path) -> Result<(), Error> {
+    let mut pending = vec![(from.to_path_buf(), to.to_path_buf())];
+    while let Some((from, to)) = pending.pop() {
+        let file_type = fs::symlink_metadata(&from).await?.file_type();
+        if file_type.is_file() {
+            // Keeps the mode, so binaries stay executable
+            fs::copy(&from, &to).await?;
+        } else if file_type.is_dir() {
+            fs::create_dir_all(&to).await?;
+            let mut dir = fs::read_dir(&from).await?;
+            while let Some(item) = dir.next_entry().await? {
+                pending.push((item.path(), to.join(item.file_name())));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Bytes taken by the regular files under `path`.
+async fn tree_size(path: &Path) -> Result<u64, Error> {
+    let mut size = 0;
+    let mut pending = vec![path.to_path_buf()];
+    while let Some(path) = pending.pop() {
+        let metadata = fs::symlink_metadata(&path).await?;
+        if metadata.is_dir() {
+            let mut dir = fs::read_dir(&path).await?;
+            while let Some(item) = dir.next_entry().await? {
+                pending.push(item.path());
+            }
+        } else if metadata.is_file() {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
+
+/// Marks the entry holding `path` as just used.
+async fn touch(path: PathBuf) {
+    let touched = task::spawn_blocking(move || {
+        std::fs::File::options()
+            .write(true)
+            .open(&path)?
+            .set_modified(SystemTime::now())
+    })
+    .await;
+    if let Ok(Err(e)) = touched {
+        tracing::debug!("Failed to mark compiled artifacts as used: {:?}", e);
+    }
+}
@@ -1,21 +1,37 @@
+use super::artifacts::ArtifactCache;
 use crate::{
-    client::redis::RedisClient,
-    core::{Job, JobStatus},
+    client::{archive::JobArchive, JobStore},
+    core::{metrics::metrics, unix_millis, BenchmarkStats, Job, JobStatus, RunSample},
 };
-use futures::TryFutureExt;
-use redis::RedisError;
 use std::{
-    fs::{self, File},
+    fs::File,
     io::Error,
     path::Path,
+    sync::Arc,
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
-use tokio::process::Command;
-use tracing;
+// Jobs run on the same runtime as the API, so file and process work here
+// must not block its threads
+use tokio::{fs, process::Command};
+use tracing::{self, Instrument};
+
+/// Wall-clock seconds a run may take, unless its deadline leaves less.
+const WALL_TIME_LIMIT: f64 = 10.0;
+
+/// Wall-clock limit of a run of `job` starting at `now`, in milliseconds
+/// since the epoch: the time left before its deadline if that is shorter,
+/// so a retried or benchmarked job doesn't run on far past it.
+fn wall_time_limit(job: &Job, now: i64) -> f64 {
+    match job.deadline {
+        Some(deadline) => WALL_TIME_LIMIT.min((deadline - now).max(1) as f64 / 1000.0),
+        None => WALL_TIME_LIMIT,
+    }
+}
 
 #[derive(Debug)]
 pub struct Metadata {
     pub time: f64,
+    pub wall_time: f64,
     pub memory: u64,
     pub exit_code: i32,
     pub message: String,
@@ -24,19 +40,119 @@ pub struct Metadata {
 
 #[derive(Clone)]
 pub struct IsolateExecutor {
-    redis: RedisClient,
+    store: Option<Arc<dyn JobStore>>,
+    archive: Option<JobArchive>,
+    artifacts: Option<ArtifactCache>,
+    result_ttl: Option<Duration>,
+    box_root: String,
+    box_id: Option<u64>,
 }
 
 impl IsolateExecutor {
-    pub fn new(redis: RedisClient) -> Self {
-        Self { redis }
+    pub fn new(store: Arc<dyn JobStore>) -> Self {
+        Self {
+            store: Some(store),
+            ..Self::standalone()
+        }
+    }
+
+    /// An executor that stores nothing, for running jobs locally.
+    pub fn standalone() -> Self {
+        Self {
+            store: None,
+            archive: None,
+            artifacts: None,
+            result_ttl: None,
+            box_root: "/var/local/lib/isolate".to_string(),
+            box_id: None,
+        }
+    }
+
+    /// Runs every job in this box instead of one derived from the job id.
+    pub fn with_box_id(mut self, box_id: u64) -> Self {
+        self.box_id = Some(box_id);
+        self
+    }
+
+    /// The box the job runs in.
+    pub fn box_id(&self, job: &Job) -> u64 {
+        self.box_id.unwrap_or(job.id % 2147483647)
+    }
+
+    /// Directory isolate creates its boxes in, as set in its configuration.
+    pub fn with_box_root(mut self, box_root: String) -> Self {
+        self.box_root = box_root;
+        self
+    }
+
+    /// Expires finished jobs from the store after `ttl`.
+    pub fn with_result_ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.result_ttl = ttl;
+        self
     }
 
+    /// Also records every finished job in the durable archive.
+    pub fn with_archive(mut self, archive: JobArchive) -> Self {
+        self.archive = Some(archive);
+        self
+    }
+
+    /// Reuses the output of earlier compiles of the same source.
+    pub fn with_artifact_cache(mut self, artifacts: ArtifactCache) -> Self {
+        self.artifacts = Some(artifacts);
+        self
+    }
+
+    /// Compiles every job, for canaries, which must exercise the toolchain
+    /// rather than binaries it built before.
+    pub fn without_artifact_cache(mut self) -> Self {
+        self.artifacts = None;
+        self
+    }
+
+    /// Runs the job and stores its result.
     #[tracing::instrument(skip(self, job), fields(job_id = job.id), level = "info")]
     pub async fn execute(&self, job: &mut Job) -> Result<JobStatus, Error> {
+        let status = self.run_job(job).await?;
+
+        let redis_update_start = SystemTime::now();
+        self.update_job_in_redis(job).await.map_err(|e| {
+            tracing::error!("Error updating job in Redis: {:?}", e);
+            Error::new(std::io::ErrorKind::Other, "Failed to update job in Redis")
+        })?;
+        let redis_update_duration = redis_update_start.elapsed().unwrap_or(Duration::from_secs(0));
+        tracing::info!("Final Redis update took {:?}", redis_update_duration);
+
+        Ok(status)
+    }
+
+    /// Stores the job with the verdict and output of `result`, an identical
+    /// job that ran, without running it.
+    pub async fn resolve(&self, job: &mut Job, result: &Job) -> Result<(), Error> {
+        job.copy_result(result);
+        self.update_job_in_redis(job).await
+    }
+
+    /// Stores the job as expired without running it.
+    pub async fn expire(&self, job: &mut Job) -> Result<(), Error> {
+        job.status = JobStatus::Expired;
+        self.update_job_in_redis(job).await
+    }
+
+    /// Stores the job as an internal error, for one that could not be run.
+    pub async fn fail(&self, job: &mut Job, message: String) -> Result<(), Error> {
+        job.status = JobStatus::InternalError;
+        job.output.message = Some(message);
+        self.update_job_in_redis(job).await
+    }
+
+    /// Compiles and runs the job in a fresh box, filling in its status and
+    /// output. Nothing is stored; see [`IsolateExecutor::execute`]. The box is
+    /// left for the caller to clean up.
+    pub async fn run_job(&self, job: &mut Job) -> Result<JobStatus, Error> {
         let total_start_time = SystemTime::now();
         
-        let box_id = job.id % 2147483647;
+        let box_id = self.box_id(job);
         job.status = JobStatus::Processing;
         job.started_at = Some(
             SystemTime::now()
@@ -54,19 +170,22 @@ impl IsolateExecutor {
         let init_output = Command::new("isolate")
             .args(&["-b", &box_id.to_string(), "--cg", "--init"])
             .output()
+            .instrument(tracing::info_span!("init", box_id))
             .await
             .map_err(|e| {
                 tracing::error!("Failed to initialize isolate box {}: {:?}", box_id, e);
+                metrics().box_init_failures.inc();
                 Error::new(std::io::ErrorKind::Other, format!("Failed to initialize box: {:?}", e))
             })?;
         let init_duration = init_start_time.elapsed().unwrap_or(Duration::from_secs(0));
         tracing::info!("Box initialization took {:?}", init_duration);
+        metrics().observe_phase("init", init_duration);
 
         if !init_output.status.success() {
+            metrics().box_init_failures.inc();
             let stderr = String::from_utf8_lossy(&init_output.stderr);
             tracing::error!("Isolate initialization failed: {}", stderr);
             job.status = JobStatus::InternalError;
-            self.update_job_in_redis(job).await?;
             return Ok(JobStatus::InternalError);
         }
 
@@ -76,24 +195,39 @@ impl IsolateExecutor {
         if box_path.is_empty() {
             tracing::error!("Empty box path returned from isolate init");
             job.status = JobStatus::InternalError;
-            self.update_job_in_redis(job).await?;
             return Ok(JobStatus::InternalError);
         }
 
         let file_setup_start = SystemTime::now();
         let (file_path, metadata_file, stdin_file, stdout_file, stderr_file) =
-            self.setup_files(job, &box_path).map_err(|e| {
+            self.setup_files(job, &box_path).await.map_err(|e| {
                 tracing::error!("Error setting up files: {:?}", e);
                 e
             })?;
         let file_setup_duration = file_setup_start.elapsed().unwrap_or(Duration::from_secs(0));
         tracing::info!("File setup took {:?}", file_setup_duration);
+        metrics().observe_phase("setup", file_setup_duration);
 
         tracing::debug!("Files set up for job {}", job.id);
 
+        // Programs compiled before are copied in instead
+        let artifacts = match self.artifacts.as_ref().filter(|_| job.language.compile_cmd.is_some()) {
+            Some(artifacts) => Some((artifacts, ArtifactCache::key(&job.language, &job.source_code).await)),
+            None => None,
+        };
+        let mut restored = false;
+        if let Some((artifacts, key)) = &artifacts {
+            if let Some(compile_output) = artifacts.restore(key, Path::new(&file_path)).await {
+                tracing::info!("Reusing compiled artifacts for job {}", job.id);
+                job.output.compile_output = Some(compile_output);
+                restored = true;
+            }
+        }
+
         // Run compilation if needed
-        if let Some(compile_cmd) = &job.language.compile_cmd {
+        if let Some(compile_cmd) = job.language.compile_cmd.as_ref().filter(|_| !restored) {
             let compile_start = SystemTime::now();
+            job.timings.compile_started_at = Some(unix_millis());
             tracing::info!("Compiling {} code for job {}", job.language.name, job.id);
             let compile_parts: Vec<&str> = compile_cmd.split_whitespace().collect();
             let compile_executable = compile_parts[0];
@@ -135,19 +269,25 @@ impl IsolateExecutor {
                     )
                     .as_str(),
                 ])
+                // Stops the sandbox if the worker abandons the job
+                .kill_on_drop(true)
                 .output()
+                .instrument(tracing::info_span!("compile"))
                 .await
                 .map_err(|e| {
                     tracing::error!("Error running compilation: {:?}", e);
                     Error::new(std::io::ErrorKind::Other, format!("Failed to run compilation: {:?}", e))
                 })?;
+            job.timings.compile_finished_at = Some(unix_millis());
             let compile_duration = compile_start.elapsed().unwrap_or(Duration::from_secs(0));
             tracing::info!("Compilation took {:?}", compile_duration);
+            metrics().observe_phase("compile", compile_duration);
 
             let output_reading_start = SystemTime::now();
             let compile_output_path = format!("{}/compile_output", file_path);
-            if Path::new(&compile_output_path).exists() {
+            if exists(&compile_output_path).await {
                 let compile_output = fs::read_to_string(&compile_output_path)
+                    .await
                     .map_err(|e| {
                         tracing::error!("Error reading compile output from {}: {:?}", compile_output_path, e);
                         Error::new(std::io::ErrorKind::Other, format!("Failed to read compile output for job {}", job.id))
@@ -158,14 +298,6 @@ impl IsolateExecutor {
                 if !compile_status.status.success() {
                     tracing::info!("Compilation failed for job {}: {}", job.id, compile_output);
                     job.status = JobStatus::CompilationError;
-                    
-                    let redis_update_start = SystemTime::now();
-                    self.update_job_in_redis(job).await.map_err(|e| {
-                        tracing::error!("Error updating job in Redis: {:?}", e);
-                        Error::new(std::io::ErrorKind::Other, "Failed to update job in Redis")
-                    })?;
-                    let redis_update_duration = redis_update_start.elapsed().unwrap_or(Duration::from_secs(0));
-                    tracing::info!("Redis update took {:?}", redis_update_duration);
 
                     let total_duration = total_start_time.elapsed().unwrap_or(Duration::from_secs(0));
                     tracing::info!("Total execution time for failed compilation: {:?}", total_duration);
@@ -175,11 +307,6 @@ impl IsolateExecutor {
                 tracing::error!("Compilation failed but compile_output file not found");
                 job.status = JobStatus::CompilationError;
                 job.output.compile_output = Some(String::from_utf8_lossy(&compile_status.stderr).to_string());
-                
-                let redis_update_start = SystemTime::now();
-                self.update_job_in_redis(job).await?;
-                let redis_update_duration = redis_update_start.elapsed().unwrap_or(Duration::from_secs(0));
-                tracing::info!("Redis update took {:?}", redis_update_duration);
 
                 let total_duration = total_start_time.elapsed().unwrap_or(Duration::from_secs(0));
                 tracing::info!("Total execution time for failed compilation: {:?}", total_duration);
@@ -187,71 +314,39 @@ impl IsolateExecutor {
             }
             let output_reading_duration = output_reading_start.elapsed().unwrap_or(Duration::from_secs(0));
             tracing::info!("Reading compilation output took {:?}", output_reading_duration);
+
+            if let Some((artifacts, key)) = &artifacts {
+                let inputs = [job.language.source_file.as_str(), "stdin", "metadata", "compile_output"];
+                let compile_output = job.output.compile_output.as_deref().unwrap_or_default();
+                artifacts.store(key, Path::new(&file_path), &inputs, compile_output).await;
+            }
         }
 
         tracing::info!("Executing job {}", job.id);
-        let run_parts: Vec<&str> = job.language.run_cmd.split_whitespace().collect();
-        let run_executable = run_parts[0];
-        let run_args = &run_parts[1..];
-
         let execution_start = SystemTime::now();
-        let run_output = Command::new("isolate")
-            .args(&[
-                "--cg",
-                "-b",
-                &box_id.to_string(),
-                "-M",
-                &metadata_file,
-                "--process=60",
-                "-t",
-                &job.settings.cpu_time_limit.to_string(),
-                "-x",
-                "0",
-                "-w",
-                "10",
-                "-k",
-                "128000",
-                format!("--cg-mem={}", job.settings.memory_limit.to_string()).as_str(),
-                "-E",
-                "PATH=\"/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin\"",
-                "-E",
-                "HOME=/tmp",
-                "-d",
-                "/etc:noexec",
-                "--run",
-                "--",
-                "/usr/bin/sh",
-                "-c",
-                format!(
-                    "{} {} > /box/stdout 2> /box/stderr",
-                    run_executable,
-                    run_args.join(" ")
-                )
-                .as_str(),
-            ])
-            .stdin(stdin_file)
-            .output()
-            .await
-            .map_err(|e| {
-                tracing::error!("Error executing job {}: {:?}", job.id, e);
-                Error::new(std::io::ErrorKind::Other, format!("Failed to run job: {:?}", e))
-            })?;
+        job.timings.run_started_at = Some(unix_millis());
+        let run_output = self
+            .run_program(job, box_id, &metadata_file, stdin_file)
+            .instrument(tracing::info_span!("run"))
+            .await?;
+        job.timings.run_finished_at = Some(unix_millis());
         let execution_duration = execution_start.elapsed().unwrap_or(Duration::from_secs(0));
         tracing::info!("Execution took {:?}", execution_duration);
+        metrics().observe_phase("run", execution_duration);
 
         tracing::debug!("Job {} execution completed with status: {}", job.id, run_output.status);
 
         let output_reading_start = SystemTime::now();
-        if Path::new(&stdout_file).exists() {
-            let stdout_content = fs::read_to_string(&stdout_file).unwrap_or_else(|e| {
+        if exists(&stdout_file).await {
+            let stdout_content = fs::read_to_string(&stdout_file).await.unwrap_or_else(|e| {
                 tracing::error!("Error reading stdout from {}: {:?}", stdout_file, e);
                 String::new()
             });
             job.output.stdout = Some(stdout_content);
         }
 
-        if Path::new(&stderr_file).exists() {
-            let stderr_content = fs::read_to_string(&stderr_file).unwrap_or_else(|e| {
+        if exists(&stderr_file).await {
+            let stderr_content = fs::read_to_string(&stderr_file).await.unwrap_or_else(|e| {
                 tracing::error!("Error reading stderr from {}: {:?}", stderr_file, e);
                 String::new()
             });
@@ -263,7 +358,7 @@ impl IsolateExecutor {
         tracing::info!("Reading output files took {:?}", output_reading_duration);
 
         let metadata_start = SystemTime::now();
-        let metadata = match self.get_metadata(box_id) {
+        let metadata = match self.get_metadata(box_id).await {
             Ok(meta) => {
                 tracing::debug!("Retrieved metadata for job {}: {:?}", job.id, meta);
                 meta
@@ -271,12 +366,12 @@ impl IsolateExecutor {
             Err(e) => {
                 tracing::error!("Error getting metadata for job {}: {:?}", job.id, e);
                 job.status = JobStatus::InternalError;
-                self.update_job_in_redis(job).await?;
                 return Ok(JobStatus::InternalError);
             }
         };
         let metadata_duration = metadata_start.elapsed().unwrap_or(Duration::from_secs(0));
         tracing::info!("Reading metadata took {:?}", metadata_duration);
+        metrics().observe_phase("metadata", metadata_duration);
 
         job.finished_at = Some(
             SystemTime::now()
@@ -292,6 +387,8 @@ impl IsolateExecutor {
         job.output.exit_code = Some(metadata.exit_code);
         job.output.message = Some(metadata.message.clone());
         
+        let first_run = RunSample::from(&metadata);
+
         let status_determination_start = SystemTime::now();
         let stdout = job.output.stdout.clone().unwrap_or_default();
         job.status = determine_status(
@@ -305,13 +402,14 @@ impl IsolateExecutor {
 
         tracing::info!("Job {} completed with status: {:?}", job.id, job.status);
 
-        let redis_update_start = SystemTime::now();
-        self.update_job_in_redis(job).await.map_err(|e| {
-            tracing::error!("Error updating job in Redis: {:?}", e);
-            Error::new(std::io::ErrorKind::Other, "Failed to update job in Redis")
-        })?;
-        let redis_update_duration = redis_update_start.elapsed().unwrap_or(Duration::from_secs(0));
-        tracing::info!("Final Redis update took {:?}", redis_update_duration);
+        if job.benchmark {
+            let benchmark_start = SystemTime::now();
+            let stdin_path = format!("{}/stdin", file_path);
+            self.run_benchmark(job, box_id, &metadata_file, &stdin_path, first_run)
+                .await?;
+            let benchmark_duration = benchmark_start.elapsed().unwrap_or(Duration::from_secs(0));
+            tracing::info!("Benchmark runs took {:?}", benchmark_duration);
+        }
 
         let total_duration = total_start_time.elapsed().unwrap_or(Duration::from_secs(0));
         tracing::info!("Total job execution took {:?}", total_duration);
@@ -319,29 +417,153 @@ impl IsolateExecutor {
         Ok(job.status.clone())
     }
 
-    async fn update_job_in_redis(&self, job: &Job) -> Result<(), Error> {
+    async fn run_program(
+        &self,
+        job: &Job,
+        box_id: u64,
+        metadata_file: &str,
+        stdin_file: File,
+    ) -> Result<std::process::Output, Error> {
+        let run_parts: Vec<&str> = job.language.run_cmd.split_whitespace().collect();
+        let run_executable = run_parts[0];
+        let run_args = &run_parts[1..];
+        let wall_time = wall_time_limit(job, unix_millis());
+
+        Command::new("isolate")
+            .args(&[
+                "--cg",
+                "-b",
+                &box_id.to_string(),
+                "-M",
+                metadata_file,
+                "--process=60",
+                "-t",
+                &job.settings.cpu_time_limit.to_string(),
+                "-x",
+                "0",
+                "-w",
+                &wall_time.to_string(),
+                "-k",
+                "128000",
+                format!("--cg-mem={}", job.settings.memory_limit.to_string()).as_str(),
+                "-E",
+                "PATH=\"/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin\"",
+                "-E",
+                "HOME=/tmp",
+                "-d",
+                "/etc:noexec",
+                "--run",
+                "--",
+                "/usr/bin/sh",
+                "-c",
+                format!(
+                    "{} {} > /box/stdout 2> /box/stderr",
+                    run_executable,
+                    run_args.join(" ")
+                )
+                .as_str(),
+            ])
+            .stdin(stdin_file)
+            .kill_on_drop(true)
+            .output()
+            .await
+            .map_err(|e| {
+                tracing::error!("Error executing job {}: {:?}", job.id, e);
+                Error::new(std::io::ErrorKind::Other, format!("Failed to run job: {:?}", e))
+            })
+    }
+
+    /// Runs the program for the remaining `number_of_runs - 1` iterations and
+    /// records the per-run samples and their statistics on the job output.
+    /// Only runs that completed normally are repeated; the verdict is left as
+    /// determined by the first run.
+    async fn run_benchmark(
+        &self,
+        job: &mut Job,
+        box_id: u64,
+        metadata_file: &str,
+        stdin_path: &str,
+        first_run: RunSample,
+    ) -> Result<(), Error> {
+        let mut samples = vec![first_run];
+
+        if matches!(job.status, JobStatus::Accepted | JobStatus::WrongAnswer) {
+            for run in 1..job.number_of_runs {
+                let stdin_file = open_std(stdin_path).await.map_err(|e| {
+                    tracing::error!("Error opening stdin file {}: {:?}", stdin_path, e);
+                    Error::new(std::io::ErrorKind::Other, format!("Failed to open stdin file: {}", e))
+                })?;
+                self.run_program(job, box_id, metadata_file, stdin_file).await?;
+
+                match self.get_metadata(box_id).await {
+                    Ok(meta) => samples.push(RunSample::from(&meta)),
+                    Err(e) => {
+                        tracing::warn!(
+                            "No metadata for benchmark run {} of job {}: {:?}",
+                            run + 1,
+                            job.id,
+                            e
+                        );
+                        break;
+                    }
+                }
+            }
+        }
+
+        job.output.benchmark = Some(BenchmarkStats::from_samples(&samples));
+        job.output.runs = samples;
+        Ok(())
+    }
+
+    async fn update_job_in_redis(&self, job: &mut Job) -> Result<(), Error> {
+        // Early verdicts (compilation and internal errors) skip the run that
+        // sets this, but the finished index and the archive need it
+        if job.finished_at.is_none() {
+            job.finished_at = Some(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs() as i64,
+            );
+        }
+        job.timings.finished_at = Some(unix_millis());
+
+        let Some(store) = &self.store else {
+            return Ok(());
+        };
+
         let redis_start = SystemTime::now();
-        let result = self.redis.store_job(&job.id.to_string(), job, None).await.map_err(|e| {
+        let result = store.store_job(&job.id.to_string(), job, self.result_ttl).await.map_err(|e| {
             tracing::error!("Error storing job {} in Redis: {:?}", job.id, e);
             Error::new(std::io::ErrorKind::Other, format!("Failed to store job in Redis: {}", e))
         });
         let redis_duration = redis_start.elapsed().unwrap_or(Duration::from_secs(0));
         tracing::debug!("Redis store operation took {:?}", redis_duration);
+        metrics().observe_phase("store", redis_duration);
+
+        // Every update reaching this point carries a final verdict. Archive
+        // failures are logged but must not fail the job, the store has the result.
+        if let Some(archive) = &self.archive {
+            if let Err(e) = archive.archive_job(job).await {
+                tracing::error!("Error archiving job {}: {}", job.id, e);
+            }
+        }
+
         result
     }
 
-    fn get_metadata(&self, box_id: u64) -> Result<Metadata, Error> {
+    async fn get_metadata(&self, box_id: u64) -> Result<Metadata, Error> {
         let start_time = SystemTime::now();
         
-        let metadata_file = format!("/var/local/lib/isolate/{}/box/metadata", box_id);
-        if !Path::new(&metadata_file).exists() {
+        let metadata_file = format!("{}/{}/box/metadata", self.box_root, box_id);
+        if !exists(&metadata_file).await {
             return Err(Error::new(
                 std::io::ErrorKind::NotFound, 
                 format!("Metadata file not found at {}", metadata_file)
             ));
         }
         
-        let metadata = fs::read_to_string(&metadata_file).map_err(|e| {
+        let metadata = fs::read_to_string(&metadata_file).await.map_err(|e| {
             tracing::error!("Error reading metadata file {}: {:?}", metadata_file, e);
             Error::new(std::io::ErrorKind::Other, format!("Failed to read metadata: {}", e))
         })?;
@@ -358,6 +580,7 @@ impl IsolateExecutor {
 
         let mut m = Metadata {
             time: 0.0,
+            wall_time: 0.0,
             memory: 0,
             exit_code: 0,
             message: "".to_string(),
@@ -372,6 +595,12 @@ impl IsolateExecutor {
                         0.0
                     })
                 },
+                "time-wall" => {
+                    m.wall_time = value.parse().unwrap_or_else(|_| {
+                        tracing::warn!("Failed to parse time-wall value: {}", value);
+                        0.0
+                    })
+                },
                 "max-rss" => {
                     m.memory = value.parse().unwrap_or_else(|_| {
                         tracing::warn!("Failed to parse max-rss value: {}", value);
@@ -402,7 +631,7 @@ impl IsolateExecutor {
         Ok(m)
     }
 
-    fn setup_files(
+    async fn setup_files(
         &self,
         job: &Job,
         box_path: &str,
@@ -419,7 +648,7 @@ impl IsolateExecutor {
         let source_path = format!("{}/{}", file_path, job.language.source_file);
         
         let source_write_start = SystemTime::now();
-        fs::write(&source_path, &job.source_code).map_err(|e| {
+        fs::write(&source_path, &job.source_code).await.map_err(|e| {
             tracing::error!("Error writing source code to {}: {:?}", source_path, e);
             Error::new(std::io::ErrorKind::Other, format!("Failed to write source code: {}", e))
         })?;
@@ -427,7 +656,7 @@ impl IsolateExecutor {
         tracing::debug!("Writing source code took {:?}", source_write_duration);
 
         let stdin_write_start = SystemTime::now();
-        fs::write(&stdin_file, &job.stdin).map_err(|e| {
+        fs::write(&stdin_file, &job.stdin).await.map_err(|e| {
             tracing::error!("Error writing stdin to {}: {:?}", stdin_file, e);
             Error::new(std::io::ErrorKind::Other, format!("Failed to write stdin: {}", e))
         })?;
@@ -435,7 +664,7 @@ impl IsolateExecutor {
         tracing::debug!("Writing stdin took {:?}", stdin_write_duration);
 
         let stdin_open_start = SystemTime::now();
-        let stdin_file = File::open(&stdin_file).map_err(|e| {
+        let stdin_file = open_std(&stdin_file).await.map_err(|e| {
             tracing::error!("Error opening stdin file {}: {:?}", stdin_file, e);
             Error::new(std::io::ErrorKind::Other, format!("Failed to open stdin file: {}", e))
         })?;
@@ -455,11 +684,33 @@ impl IsolateExecutor {
     }
 }
 
+/// Whether `path` exists, checked on the blocking pool.
+async fn exists(path: &str) -> bool {
+    fs::try_exists(path).await.unwrap_or(false)
+}
+
+/// Opens `path` on the blocking pool, as the std file a child process's
+/// stdin is made from.
+async fn open_std(path: &str) -> Result<File, Error> {
+    Ok(fs::File::open(path).await?.into_std().await)
+}
+
+impl From<&Metadata> for RunSample {
+    fn from(meta: &Metadata) -> Self {
+        Self {
+            time: meta.time,
+            wall_time: meta.wall_time,
+            memory: meta.memory,
+            exit_code: meta.exit_code,
+        }
+    }
+}
+
 fn determine_status(
     status: String,
     exitcode: i32,
-    stdout: &String,
-    expected: &String,
+    stdout: &str,
+    expected: &str,
 ) -> JobStatus {
     match status.as_str() {
         "TO" => JobStatus::TimeLimitExceeded,
@@ -479,4 +730,19 @@ fn find_typeof_runtime(exitcode: i32) -> JobStatus {
         6 => JobStatus::RuntimeError("SIGABRT".to_string()),
         _ => JobStatus::RuntimeError("Other".to_string()),
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caps_the_wall_time_at_the_deadline() {
+        let job = Job::default();
+        assert_eq!(wall_time_limit(&job, 0), WALL_TIME_LIMIT);
+        let job = job.with_deadline(60_000);
+        assert_eq!(wall_time_limit(&job, 0), WALL_TIME_LIMIT);
+        assert_eq!(wall_time_limit(&job, 57_500), 2.5);
+        assert_eq!(wall_time_limit(&job, 61_000), 0.001);
+    }
+}
@@ -1,3 +1,6 @@
+pub mod artifacts;
+#[allow(clippy::io_other_error, clippy::needless_borrows_for_generic_args, clippy::to_string_in_format_args)]
 pub mod isolate;
-pub mod debugger;
+pub mod debugger;
+pub mod preflight;
 // pub mod sqlizer;
\ No newline at end of file
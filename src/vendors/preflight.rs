@@ -0,0 +1,492 @@
+//! Checks the host for problems that stop isolate from enforcing limits or
+//! make timings unreliable. A port of `check_env.sh`, run by workers at
+//! startup.
+
+use serde::Serialize;
+use std::{fs, path::Path, process::Command};
+
+/// Oldest isolate release with cgroup v2 support, which `--cg` relies on.
+const MIN_ISOLATE_VERSION: (u32, u32) = (2, 0);
+
+/// Files isolate needs in its cgroup root to run with `--cg`.
+const CGROUP_FILES: [&str; 6] = [
+    "cpuset.cpus",
+    "cpuset.mems",
+    "cpu.stat",
+    "cgroup.procs",
+    "memory.events",
+    "memory.max",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Pass,
+    /// Jobs run correctly, but timings may vary.
+    Warn,
+    /// Limits or isolation can't be relied on.
+    Fail,
+    /// Not applicable to this host.
+    Skipped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: Option<String>,
+    /// Command that fixes the problem until the next reboot.
+    pub fix: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PreflightReport {
+    pub checks: Vec<Check>,
+}
+
+impl PreflightReport {
+    pub fn failed(&self) -> bool {
+        self.checks.iter().any(|check| check.status == CheckStatus::Fail)
+    }
+
+    /// Checks that warned or failed.
+    pub fn problems(&self) -> impl Iterator<Item = &Check> {
+        self.checks
+            .iter()
+            .filter(|check| matches!(check.status, CheckStatus::Warn | CheckStatus::Fail))
+    }
+
+    fn push(&mut self, name: &'static str, status: CheckStatus) {
+        self.checks.push(Check {
+            name,
+            status,
+            detail: None,
+            fix: None,
+        });
+    }
+
+    fn problem(&mut self, name: &'static str, status: CheckStatus, detail: String, fix: Option<String>) {
+        self.checks.push(Check {
+            name,
+            status,
+            detail: Some(detail),
+            fix,
+        });
+    }
+}
+
+/// Runs every check. Blocks on file reads and `isolate` invocations.
+pub fn run() -> PreflightReport {
+    let mut report = PreflightReport::default();
+    check_isolate_version(&mut report);
+    let cg_root = check_cgroups(&mut report);
+    check_host(&mut report, Path::new("/"), cg_root.as_deref());
+    report
+}
+
+/// The checks that only read `/proc` and `/sys`, found under `root`.
+fn check_host(report: &mut PreflightReport, root: &Path, cg_root: Option<&str>) {
+    check_swap(report, root, cg_root);
+    check_cpufreq(report, root);
+    check_boost(report, root);
+    check_aslr(report, root);
+    check_hugepages(report, root);
+    check_core_pattern(report, root);
+    check_hardlinks(report, root);
+}
+
+/// Reads the host file at absolute `path` under `root`.
+fn read(root: &Path, path: &str) -> Option<String> {
+    fs::read_to_string(root.join(path.trim_start_matches('/')))
+        .ok()
+        .map(|value| value.trim().to_string())
+}
+
+fn check_isolate_version(report: &mut PreflightReport) {
+    const NAME: &str = "isolate version";
+
+    let output = match Command::new("isolate").arg("--version").output() {
+        Ok(output) if output.status.success() => output,
+        Ok(output) => {
+            let detail = format!("isolate --version exited with {}", output.status);
+            return report.problem(NAME, CheckStatus::Fail, detail, None);
+        }
+        Err(e) => {
+            let detail = format!("isolate could not be run: {}", e);
+            return report.problem(NAME, CheckStatus::Fail, detail, None);
+        }
+    };
+
+    // "The process isolator 2.0" on the first line
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let version = stdout
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().find_map(parse_version));
+    match version {
+        Some(version) if version >= MIN_ISOLATE_VERSION => report.push(NAME, CheckStatus::Pass),
+        Some((major, minor)) => report.problem(
+            NAME,
+            CheckStatus::Fail,
+            format!(
+                "isolate {}.{} is too old, {}.{} or later is needed for cgroup v2",
+                major, minor, MIN_ISOLATE_VERSION.0, MIN_ISOLATE_VERSION.1
+            ),
+            None,
+        ),
+        None => report.problem(
+            NAME,
+            CheckStatus::Warn,
+            format!("could not parse the version from {:?}", stdout.trim()),
+            None,
+        ),
+    }
+}
+
+fn parse_version(word: &str) -> Option<(u32, u32)> {
+    let mut parts = word.split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next().map_or(Some(0), |minor| minor.parse().ok())?;
+    Some((major, minor))
+}
+
+/// Returns the cgroup root isolate uses, if it has one.
+fn check_cgroups(report: &mut PreflightReport) -> Option<String> {
+    const NAME: &str = "cgroup support";
+
+    let cg_root = Command::new("isolate")
+        .arg("--print-cg-root")
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .filter(|root| !root.is_empty());
+    let Some(cg_root) = cg_root else {
+        let detail = "cgroup root not found, isolate --cg cannot be used".to_string();
+        report.problem(NAME, CheckStatus::Fail, detail, None);
+        return None;
+    };
+
+    let missing: Vec<&str> = CGROUP_FILES
+        .into_iter()
+        .filter(|file| !Path::new(&cg_root).join(file).is_file())
+        .collect();
+    if missing.is_empty() {
+        report.push(NAME, CheckStatus::Pass);
+    } else {
+        let detail = format!(
+            "{} missing under {}, isolate --cg cannot be used",
+            missing.join(", "),
+            cg_root
+        );
+        report.problem(NAME, CheckStatus::Fail, detail, None);
+    }
+    Some(cg_root)
+}
+
+fn check_swap(report: &mut PreflightReport, root: &Path, cg_root: Option<&str>) {
+    const NAME: &str = "swap";
+
+    // The first line of /proc/swaps is a header
+    let swap_enabled = read(root, "/proc/swaps").is_some_and(|swaps| swaps.lines().count() > 1);
+    if !swap_enabled {
+        return report.push(NAME, CheckStatus::Pass);
+    }
+
+    let accounted = cg_root.is_some_and(|root| Path::new(root).join("memory.swap.current").is_file());
+    if accounted {
+        report.problem(
+            NAME,
+            CheckStatus::Warn,
+            "swap is enabled and accounted for, but may still cause run-time variability under memory pressure".to_string(),
+            None,
+        );
+    } else {
+        report.problem(
+            NAME,
+            CheckStatus::Fail,
+            "swap is enabled without swap accounting, so memory limits can't be enforced".to_string(),
+            Some("swapoff -a".to_string()),
+        );
+    }
+}
+
+fn check_cpufreq(report: &mut PreflightReport, root: &Path) {
+    const NAME: &str = "CPU frequency scaling";
+    const CPUFREQ: &str = "/sys/devices/system/cpu/cpufreq";
+
+    let Ok(policies) = fs::read_dir(root.join(CPUFREQ.trim_start_matches('/'))) else {
+        return report.push(NAME, CheckStatus::Skipped);
+    };
+
+    let mut governors = 0;
+    for policy in policies.filter_map(Result::ok) {
+        let path = format!("{}/{}/scaling_governor", CPUFREQ, policy.file_name().to_string_lossy());
+        let Some(governor) = read(root, &path) else {
+            continue;
+        };
+        governors += 1;
+        if governor != "performance" {
+            return report.problem(
+                NAME,
+                CheckStatus::Warn,
+                format!("cpufreq governor set to '{}', but 'performance' would be better", governor),
+                Some(format!("echo performance > {}", path)),
+            );
+        }
+    }
+
+    let status = if governors == 0 { CheckStatus::Skipped } else { CheckStatus::Pass };
+    report.push(NAME, status);
+}
+
+fn check_boost(report: &mut PreflightReport, root: &Path) {
+    const NAME: &str = "CPU frequency boost";
+    const INTEL_NO_TURBO: &str = "/sys/devices/system/cpu/intel_pstate/no_turbo";
+    const CPUFREQ_BOOST: &str = "/sys/devices/system/cpu/cpufreq/boost";
+
+    let intel = read(root, INTEL_NO_TURBO);
+    let general = read(root, CPUFREQ_BOOST);
+    if intel.is_none() && general.is_none() {
+        return report.push(NAME, CheckStatus::Skipped);
+    }
+
+    if intel.is_some_and(|no_turbo| no_turbo != "1") {
+        return report.problem(
+            NAME,
+            CheckStatus::Warn,
+            "Intel frequency boosting is enabled".to_string(),
+            Some(format!("echo 1 > {}", INTEL_NO_TURBO)),
+        );
+    }
+    if general.is_some_and(|boost| boost != "0") {
+        return report.problem(
+            NAME,
+            CheckStatus::Warn,
+            "frequency boosting is enabled".to_string(),
+            Some(format!("echo 0 > {}", CPUFREQ_BOOST)),
+        );
+    }
+    report.push(NAME, CheckStatus::Pass);
+}
+
+fn check_aslr(report: &mut PreflightReport, root: &Path) {
+    const NAME: &str = "address space randomisation";
+    const PATH: &str = "/proc/sys/kernel/randomize_va_space";
+
+    match read(root, PATH) {
+        None => report.push(NAME, CheckStatus::Skipped),
+        Some(value) if value == "0" => report.push(NAME, CheckStatus::Pass),
+        Some(_) => report.problem(
+            NAME,
+            CheckStatus::Warn,
+            "address space randomisation is enabled".to_string(),
+            Some(format!("echo 0 > {}", PATH)),
+        ),
+    }
+}
+
+fn check_hugepages(report: &mut PreflightReport, root: &Path) {
+    const NAME: &str = "transparent hugepages";
+
+    // Whether the kernel can hand out 2 MiB pages varies from run to run
+    for (path, what) in [
+        ("/sys/kernel/mm/transparent_hugepage/enabled", "transparent hugepages are"),
+        ("/sys/kernel/mm/transparent_hugepage/defrag", "transparent hugepage defrag is"),
+    ] {
+        if read(root, path).is_some_and(|value| !value.contains("[never]")) {
+            return report.problem(
+                NAME,
+                CheckStatus::Warn,
+                format!("{} enabled", what),
+                Some(format!("echo never > {}", path)),
+            );
+        }
+    }
+
+    const KHUGEPAGED: &str = "/sys/kernel/mm/transparent_hugepage/khugepaged/defrag";
+    if read(root, KHUGEPAGED).is_some_and(|value| value != "0") {
+        return report.problem(
+            NAME,
+            CheckStatus::Warn,
+            "khugepaged defrag is enabled".to_string(),
+            Some(format!("echo 0 > {}", KHUGEPAGED)),
+        );
+    }
+    report.push(NAME, CheckStatus::Pass);
+}
+
+fn check_core_pattern(report: &mut PreflightReport, root: &Path) {
+    const NAME: &str = "core file pattern";
+    const PATH: &str = "/proc/sys/kernel/core_pattern";
+
+    // Piped core dumps ignore RLIMIT_CORE and make crashes much slower
+    match read(root, PATH) {
+        None => report.push(NAME, CheckStatus::Skipped),
+        Some(pattern) if pattern.starts_with('|') => report.problem(
+            NAME,
+            CheckStatus::Warn,
+            "core files are piped to a program".to_string(),
+            Some(format!("echo core > {}", PATH)),
+        ),
+        Some(_) => report.push(NAME, CheckStatus::Pass),
+    }
+}
+
+fn check_hardlinks(report: &mut PreflightReport, root: &Path) {
+    const NAME: &str = "hard link protection";
+    const PATH: &str = "/proc/sys/fs/protected_hardlinks";
+
+    // Without it, isolate can be tricked into chowning unrelated files
+    match read(root, PATH) {
+        None => report.push(NAME, CheckStatus::Skipped),
+        Some(value) if value == "0" => report.problem(
+            NAME,
+            CheckStatus::Fail,
+            "hard link protection is disabled".to_string(),
+            Some(format!("echo 1 > {}", PATH)),
+        ),
+        Some(_) => report.push(NAME, CheckStatus::Pass),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use uuid::Uuid;
+
+    /// A fake root holding the `/proc` and `/sys` files the checks read.
+    struct Host(PathBuf);
+
+    impl Host {
+        fn new() -> Self {
+            Self(std::env::temp_dir().join(format!("flash-preflight-{}", Uuid::new_v4())))
+        }
+
+        /// A host tuned the way `check_env.sh` recommends.
+        fn tuned() -> Self {
+            let host = Self::new();
+            host.write("/proc/swaps", "Filename\tType\tSize\tUsed\tPriority\n");
+            host.write("/sys/devices/system/cpu/cpufreq/policy0/scaling_governor", "performance\n");
+            host.write("/sys/devices/system/cpu/intel_pstate/no_turbo", "1\n");
+            host.write("/proc/sys/kernel/randomize_va_space", "0\n");
+            host.write("/sys/kernel/mm/transparent_hugepage/enabled", "always madvise [never]\n");
+            host.write("/sys/kernel/mm/transparent_hugepage/defrag", "always defer madvise [never]\n");
+            host.write("/sys/kernel/mm/transparent_hugepage/khugepaged/defrag", "0\n");
+            host.write("/proc/sys/kernel/core_pattern", "core\n");
+            host.write("/proc/sys/fs/protected_hardlinks", "1\n");
+            host
+        }
+
+        fn write(&self, path: &str, contents: &str) {
+            let path = self.0.join(path.trim_start_matches('/'));
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+
+        fn check(&self, cg_root: Option<&str>) -> PreflightReport {
+            let mut report = PreflightReport::default();
+            check_host(&mut report, &self.0, cg_root);
+            report
+        }
+    }
+
+    impl Drop for Host {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn status(report: &PreflightReport, name: &str) -> CheckStatus {
+        report.checks.iter().find(|check| check.name == name).unwrap().status
+    }
+
+    fn fix<'a>(report: &'a PreflightReport, name: &str) -> Option<&'a str> {
+        report.checks.iter().find(|check| check.name == name).unwrap().fix.as_deref()
+    }
+
+    #[test]
+    fn tuned_host_passes() {
+        let report = Host::tuned().check(None);
+        assert_eq!(report.checks.len(), 7);
+        assert!(report.checks.iter().all(|check| check.status == CheckStatus::Pass));
+        assert!(!report.failed());
+    }
+
+    #[test]
+    fn missing_files_are_skipped() {
+        let report = Host::new().check(None);
+        for name in [
+            "CPU frequency scaling",
+            "CPU frequency boost",
+            "address space randomisation",
+            "hard link protection",
+        ] {
+            assert_eq!(status(&report, name), CheckStatus::Skipped, "{}", name);
+        }
+        assert_eq!(report.problems().count(), 0);
+    }
+
+    #[test]
+    fn swap_fails_without_accounting() {
+        let host = Host::tuned();
+        host.write("/proc/swaps", "Filename\tType\tSize\tUsed\tPriority\n/swapfile\tfile\t1024\t0\t-2\n");
+        let cg_root = host.0.join("cgroup");
+        fs::create_dir_all(&cg_root).unwrap();
+        let cg_root = cg_root.to_str().unwrap();
+
+        let report = host.check(Some(cg_root));
+        assert_eq!(status(&report, "swap"), CheckStatus::Fail);
+        assert_eq!(fix(&report, "swap"), Some("swapoff -a"));
+        assert!(report.failed());
+
+        host.write("/cgroup/memory.swap.current", "0\n");
+        let report = host.check(Some(cg_root));
+        assert_eq!(status(&report, "swap"), CheckStatus::Warn);
+        assert!(!report.failed());
+    }
+
+    #[test]
+    fn timing_noise_warns_with_fixes() {
+        let host = Host::tuned();
+        host.write("/sys/devices/system/cpu/cpufreq/policy0/scaling_governor", "powersave\n");
+        host.write("/sys/devices/system/cpu/intel_pstate/no_turbo", "0\n");
+        host.write("/proc/sys/kernel/randomize_va_space", "2\n");
+        host.write("/sys/kernel/mm/transparent_hugepage/enabled", "[always] madvise never\n");
+        host.write("/proc/sys/kernel/core_pattern", "|/usr/lib/systemd/systemd-coredump %P\n");
+
+        let report = host.check(None);
+        assert_eq!(report.problems().count(), 5);
+        assert!(report.problems().all(|check| check.status == CheckStatus::Warn));
+        assert!(!report.failed());
+        assert_eq!(
+            fix(&report, "CPU frequency scaling"),
+            Some("echo performance > /sys/devices/system/cpu/cpufreq/policy0/scaling_governor")
+        );
+        assert_eq!(
+            fix(&report, "transparent hugepages"),
+            Some("echo never > /sys/kernel/mm/transparent_hugepage/enabled")
+        );
+        assert_eq!(fix(&report, "core file pattern"), Some("echo core > /proc/sys/kernel/core_pattern"));
+    }
+
+    #[test]
+    fn disabled_hardlink_protection_fails() {
+        let host = Host::tuned();
+        host.write("/proc/sys/fs/protected_hardlinks", "0\n");
+
+        let report = host.check(None);
+        assert_eq!(status(&report, "hard link protection"), CheckStatus::Fail);
+        assert!(report.failed());
+    }
+
+    #[test]
+    fn parses_isolate_versions() {
+        assert_eq!(parse_version("2.0"), Some((2, 0)));
+        assert_eq!(parse_version("1.10"), Some((1, 10)));
+        assert_eq!(parse_version("2"), Some((2, 0)));
+        assert_eq!(parse_version("isolator"), None);
+        assert_eq!(parse_version("2.x"), None);
+    }
+}
@@ -0,0 +1,202 @@
+//! Built-in self-test programs that workers run to catch broken toolchains.
+//!
+//! Each language has a program that must be accepted and one that must get
+//! a wrong answer. A toolchain that fails to compile or run the first, or a
+//! judge that accepts the second, marks the language unhealthy on that
+//! worker, which then stops taking jobs in it until a later check passes.
+
+use super::{cleanup_box, registry::WorkerLoad};
+use crate::{
+    core::{metrics::metrics, CanaryResult, Job, JobStatus, Language},
+    vendors::isolate::IsolateExecutor,
+};
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// Output the canary programs print.
+const EXPECTED_OUTPUT: &str = "canary";
+/// Longest a canary job may take, compile included, before it counts as failed.
+const CANARY_TIMEOUT: Duration = Duration::from_secs(60);
+
+struct Canary {
+    name: &'static str,
+    source: &'static str,
+    verdict: JobStatus,
+}
+
+/// The canaries of `language`. SQL has none until its runner is done.
+fn canaries(language: &str) -> Vec<Canary> {
+    let (good, bad) = match language {
+        "python" => ("print('canary')", "print('nope')"),
+        "cpp" => (
+            "#include <cstdio>\nint main() { std::puts(\"canary\"); return 0; }\n",
+            "#include <cstdio>\nint main() { std::puts(\"nope\"); return 0; }\n",
+        ),
+        "javascript" => ("console.log('canary')", "console.log('nope')"),
+        "java" => (
+            "public class Main { public static void main(String[] args) { System.out.println(\"canary\"); } }",
+            "public class Main { public static void main(String[] args) { System.out.println(\"nope\"); } }",
+        ),
+        _ => return Vec::new(),
+    };
+    vec![
+        Canary {
+            name: "known-good",
+            source: good,
+            verdict: JobStatus::Accepted,
+        },
+        Canary {
+            name: "known-bad",
+            source: bad,
+            verdict: JobStatus::WrongAnswer,
+        },
+    ]
+}
+
+/// Runs the canaries of `language` in the executor's box. Returns `None`
+/// for languages without canaries.
+pub async fn check_language(executor: &IsolateExecutor, language: &str) -> Option<CanaryResult> {
+    let canaries = canaries(language);
+    let template = Language::from_name(language)?;
+    if canaries.is_empty() {
+        return None;
+    }
+
+    let mut failure = None;
+    for canary in canaries {
+        let mut job = Job::new(canary.source.to_string(), template.clone())
+            .with_expected_output(EXPECTED_OUTPUT.to_string());
+        let result = tokio::time::timeout(CANARY_TIMEOUT, executor.run_job(&mut job)).await;
+        cleanup_box(executor.box_id(&job)).await;
+
+        let problem = match result {
+            Err(_) => Some(format!("timed out after {:?}", CANARY_TIMEOUT)),
+            Ok(Err(e)) => Some(format!("failed to run: {}", e)),
+            Ok(Ok(_)) if job.status != canary.verdict => Some(format!(
+                "expected {}, got {}{}",
+                canary.verdict,
+                job.status,
+                job.output
+                    .compile_output
+                    .as_deref()
+                    .filter(|output| !output.is_empty())
+                    .map(|output| format!(": {}", output.trim()))
+                    .unwrap_or_default()
+            )),
+            Ok(Ok(_)) => None,
+        };
+        if let Some(problem) = problem {
+            failure = Some(format!("{} program {}", canary.name, problem));
+            break;
+        }
+    }
+    Some(CanaryResult::new(failure))
+}
+
+/// Checks each of `languages` every `interval` until `stop` turns true,
+/// recording the results in `load` for the job slots and the heartbeat.
+pub async fn run_canaries(
+    executor: IsolateExecutor,
+    languages: Vec<String>,
+    load: WorkerLoad,
+    interval: Duration,
+    mut stop: watch::Receiver<bool>,
+) {
+    loop {
+        for language in &languages {
+            let Some(result) = check_language(&executor, language).await else {
+                continue;
+            };
+
+            let was_healthy = load
+                .canaries
+                .lock()
+                .unwrap()
+                .get(language)
+                .is_none_or(|previous| previous.healthy);
+            match (&result.detail, was_healthy) {
+                (Some(detail), true) => {
+                    eprintln!("Canary for {} failed, no longer taking its jobs: {}", language, detail)
+                }
+                (None, false) => println!("Canary for {} passed again, taking its jobs", language),
+                _ => {}
+            }
+            if !result.healthy {
+                metrics().canary_failures.with_label_values(&[language]).inc();
+            }
+            metrics()
+                .language_healthy
+                .with_label_values(&[language])
+                .set(result.healthy as i64);
+            load.canaries.lock().unwrap().insert(language.clone(), result);
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = stop.wait_for(|stop| *stop) => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vendors::artifacts::ArtifactCache;
+    use std::{fs, os::unix::fs::PermissionsExt, path::PathBuf};
+    use uuid::Uuid;
+
+    /// Stands in for isolate: "compiles" C++ by copying the source, and the
+    /// program prints `canary` if its source does. Compiles fail once a
+    /// `broken` file exists next to it.
+    const FAKE_ISOLATE: &str = r#"#!/bin/sh
+dir=$(dirname "$0")
+b=$(echo "$*" | sed 's/.*-b \([0-9]*\).*/\1/')
+box=$dir/boxes/$b/box
+case "$*" in
+  *--init*) mkdir -p "$box"; echo "$dir/boxes/$b" ;;
+  *--cleanup*) rm -rf "$dir/boxes/$b" ;;
+  *g++*)
+    if [ -e "$dir/broken" ]; then echo "g++: internal compiler error" > "$box/compile_output"; exit 1; fi
+    cp "$box/main.cpp" "$box/a.out"; : > "$box/compile_output" ;;
+  *--run*)
+    if grep -q '"canary"' "$box/a.out" 2>/dev/null; then echo canary; else echo nope; fi > "$box/stdout"
+    printf 'time:0.01\ntime-wall:0.02\nmax-rss:1000\nexitcode:0\n' > "$box/metadata" ;;
+esac
+"#;
+
+    /// Puts the fake isolate first on `PATH`, in a directory of its own.
+    fn fake_isolate() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("flash-canary-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("isolate");
+        fs::write(&script, FAKE_ISOLATE).unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        let path = std::env::var("PATH").unwrap_or_default();
+        std::env::set_var("PATH", format!("{}:{}", dir.display(), path));
+        dir
+    }
+
+    #[tokio::test]
+    async fn fails_once_the_compiler_breaks_despite_cached_artifacts() {
+        let dir = fake_isolate();
+        let executor = IsolateExecutor::standalone()
+            .with_box_root(dir.join("boxes").display().to_string())
+            .with_artifact_cache(ArtifactCache::new(dir.join("artifacts"), 1 << 20))
+            .with_box_id(1);
+        let canary = executor.clone().without_artifact_cache().with_box_id(2);
+
+        // Jobs identical to the canaries leave their binaries in the cache
+        let result = check_language(&executor, "cpp").await.unwrap();
+        assert!(result.healthy, "{:?}", result.detail);
+        let result = check_language(&canary, "cpp").await.unwrap();
+        assert!(result.healthy, "{:?}", result.detail);
+
+        fs::write(dir.join("broken"), "").unwrap();
+        let result = check_language(&canary, "cpp").await.unwrap();
+        assert!(!result.healthy);
+        let detail = result.detail.unwrap();
+        assert!(detail.contains("internal compiler error"), "{}", detail);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,45 @@
+use crate::{
+    client::Backend,
+    core::{metrics::metrics, Job},
+    utils::utils::run_waiters,
+    vendors::isolate::IsolateExecutor,
+};
+use std::time::Duration;
+
+/// Ends the result cache run of `job`, if it was submitted with the cache on.
+/// A verdict an identical job would also get is cached for `ttl` and given
+/// to the jobs that waited on this one. Otherwise the waiters are queued to
+/// run on their own.
+pub async fn settle(backend: &dyn Backend, executor: &IsolateExecutor, job: &Job, ttl: Duration) {
+    let Some(fingerprint) = &job.fingerprint else {
+        return;
+    };
+    let result = job.status.is_cacheable().then_some(job);
+    let waiting = match backend.settle(fingerprint, result, ttl).await {
+        Ok(waiting) => waiting,
+        Err(e) => {
+            tracing::error!("Error settling cached result of job {}: {}", job.id, e);
+            return;
+        }
+    };
+
+    let Some(result) = result else {
+        run_waiters(backend, waiting).await;
+        return;
+    };
+    for key in waiting {
+        let mut waiter = match backend.get_job(&key).await {
+            Ok(Some(waiter)) if !waiter.is_finished() => waiter,
+            Ok(_) => continue,
+            Err(e) => {
+                tracing::error!("Error loading job {} waiting on job {}: {}", key, job.id, e);
+                continue;
+            }
+        };
+        if let Err(e) = executor.resolve(&mut waiter, result).await {
+            tracing::error!("Error storing result of job {}: {:?}", key, e);
+            continue;
+        }
+        metrics().record_verdict(&waiter.language.name, &waiter.status);
+    }
+}
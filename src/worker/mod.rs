@@ -1,84 +1,505 @@
-use crate::{client::redis::RedisClient, core::job::Job, vendors::isolate::IsolateExecutor};
-use tokio::task;
-use std::{process::Command, sync::Arc, time::Duration};
+pub mod canary;
+pub mod coalesce;
+pub mod registry;
+pub mod scheduler;
+pub mod sweeper;
+
+use crate::{
+    client::{archive::JobArchive, Backend},
+    core::{
+        metrics::metrics, telemetry, tenant::Tenants, unix_millis, ActiveJob, Language, WorkerInfo,
+    },
+    vendors::{artifacts::ArtifactCache, isolate::IsolateExecutor},
+};
+use canary::run_canaries;
+use registry::{run_heartbeat, WorkerLoad};
+use scheduler::FairScheduler;
+use futures::future::join_all;
+use tokio::{fs, process::Command, sync::watch, task};
+use tracing::Instrument;
+use std::{
+    collections::BTreeMap,
+    fs::TryLockError,
+    io,
+    ops::Range,
+    path::PathBuf,
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 pub struct Worker {
-    redis: Arc<RedisClient>,
+    backend: Arc<dyn Backend>,
     isolate_executor: IsolateExecutor,
+    languages: Arc<Vec<String>>,
+    reserved_slots: BTreeMap<String, usize>,
+    scheduler: Arc<FairScheduler>,
+    load: WorkerLoad,
+    heartbeat_interval: Duration,
+    heartbeat_ttl: Duration,
+    drain_timeout: Duration,
+    starvation_timeout: Option<Duration>,
+    canary_interval: Option<Duration>,
+    result_cache_ttl: Duration,
+    box_root: String,
+    first_box: u64,
 }
 
 impl Worker {
-    pub fn new(redis: RedisClient) -> Self {
+    pub fn new(backend: Arc<dyn Backend>) -> Self {
         Self {
-            redis: Arc::new(redis.clone()),
-            isolate_executor: IsolateExecutor::new(redis),
+            isolate_executor: IsolateExecutor::new(backend.clone()),
+            backend,
+            languages: Arc::new(Vec::new()),
+            reserved_slots: BTreeMap::new(),
+            scheduler: Arc::new(FairScheduler::new(Tenants::default())),
+            load: WorkerLoad::default(),
+            heartbeat_interval: Duration::from_secs(5),
+            heartbeat_ttl: Duration::from_secs(30),
+            drain_timeout: Duration::from_secs(30),
+            starvation_timeout: Some(Duration::from_secs(60)),
+            canary_interval: Some(Duration::from_secs(300)),
+            result_cache_ttl: Duration::from_secs(3600),
+            box_root: "/var/local/lib/isolate".to_string(),
+            first_box: 0,
         }
     }
 
-    pub async fn start(&self, concurrency: usize) {
+    /// Runs slot `n` in isolate box `first_box + n` and canaries in
+    /// `first_box + concurrency`, so the next worker on the host starts
+    /// after that box.
+    pub fn with_first_box(mut self, first_box: u64) -> Self {
+        self.first_box = first_box;
+        self
+    }
+
+    /// How long running jobs get to finish once shutdown is requested.
+    pub fn with_drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    /// How long a lower priority job may wait at the head of its queue
+    /// before it is taken ahead of higher priorities. `None` serves
+    /// priorities strictly.
+    pub fn with_starvation_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.starvation_timeout = timeout;
+        self
+    }
+
+    /// How often each language's canary programs run. `None` turns the
+    /// self-tests off.
+    pub fn with_canary_interval(mut self, interval: Option<Duration>) -> Self {
+        self.canary_interval = interval;
+        self
+    }
+
+    /// How long the verdicts of jobs submitted with the result cache on are
+    /// cached for.
+    pub fn with_result_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.result_cache_ttl = ttl;
+        self
+    }
+
+    /// How often the worker heartbeats into the registry, and how long a
+    /// silent worker is given before its jobs are requeued.
+    pub fn with_heartbeat(mut self, interval: Duration, ttl: Duration) -> Self {
+        self.heartbeat_interval = interval;
+        self.heartbeat_ttl = ttl;
+        self
+    }
+
+    /// Shares the worker's slots between these tenants by weight, within
+    /// their concurrency caps.
+    pub fn with_tenants(mut self, tenants: Tenants) -> Self {
+        self.scheduler = Arc::new(FairScheduler::new(tenants));
+        self
+    }
+
+    /// Restricts the worker to jobs in these languages. Empty means all.
+    pub fn with_languages(mut self, languages: Vec<String>) -> Self {
+        self.languages = Arc::new(languages);
+        self
+    }
+
+    /// Sets aside slots that only run one language, by language, so a burst
+    /// in another language can't take every slot.
+    pub fn with_reserved_slots(mut self, reserved_slots: BTreeMap<String, usize>) -> Self {
+        self.reserved_slots = reserved_slots;
+        self
+    }
+
+    pub fn with_archive(mut self, archive: JobArchive) -> Self {
+        self.isolate_executor = self.isolate_executor.with_archive(archive);
+        self
+    }
+
+    pub fn with_artifact_cache(mut self, artifacts: ArtifactCache) -> Self {
+        self.isolate_executor = self.isolate_executor.with_artifact_cache(artifacts);
+        self
+    }
+
+    pub fn with_result_ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.isolate_executor = self.isolate_executor.with_result_ttl(ttl);
+        self
+    }
+
+    pub fn with_box_root(mut self, box_root: String) -> Self {
+        self.isolate_executor = self.isolate_executor.with_box_root(box_root.clone());
+        self.box_root = box_root;
+        self
+    }
+
+    /// Runs `concurrency` job loops until `shutdown` turns true. Each loop
+    /// stops taking jobs once shutdown is requested and returns after its
+    /// current job finishes. Jobs still running when the drain timeout
+    /// passes are abandoned, requeued and their boxes cleaned up. The worker
+    /// is registered and heartbeats until then. Canaries run in the box
+    /// after the slots' boxes. Fails without taking jobs if another worker
+    /// on the host holds one of those boxes.
+    pub async fn start(&self, concurrency: usize, mut shutdown: watch::Receiver<bool>) -> Result<(), String> {
+        let boxes = self.first_box..self.first_box + concurrency as u64;
+        let canary_box = boxes.end;
+        let _locks = lock_boxes(&self.box_root, self.first_box..canary_box + 1).await?;
+        self.clean_stale_boxes(self.first_box..canary_box + 1).await;
+        self.route_legacy_queues().await;
+
+        let info = WorkerInfo::new(
+            concurrency,
+            self.languages.to_vec(),
+            self.reserved_slots.clone(),
+        );
+        println!("Registered as worker {}", info.id);
+        let worker_id: Arc<str> = Arc::from(info.id.as_str());
+        let (stop_heartbeat, heartbeat_stopped) = watch::channel(false);
+        let heartbeat = task::spawn(run_heartbeat(
+            Arc::clone(&self.backend),
+            info,
+            self.load.clone(),
+            self.heartbeat_interval,
+            self.heartbeat_ttl,
+            heartbeat_stopped,
+        ));
+
+        // Reserved slots first, then slots for every language this worker runs
+        let served = match self.languages.is_empty() {
+            true => Language::NAMES.map(String::from).to_vec(),
+            false => self.languages.to_vec(),
+        };
+        let canaries = self.canary_interval.map(|interval| {
+            task::spawn(run_canaries(
+                self.isolate_executor.clone().without_artifact_cache().with_box_id(canary_box),
+                served.clone(),
+                self.load.clone(),
+                interval,
+                shutdown.clone(),
+            ))
+        });
+        let mut slot_languages: Vec<Arc<Vec<String>>> = self
+            .reserved_slots
+            .iter()
+            .flat_map(|(language, slots)| std::iter::repeat_n(Arc::new(vec![language.clone()]), *slots))
+            .collect();
+        slot_languages.resize(concurrency, Arc::new(served));
+
         let mut handles = Vec::with_capacity(concurrency);
         
-        for _ in 0..concurrency {
-            let redis = Arc::clone(&self.redis);
-            let executor = self.isolate_executor.clone();
+        for (box_id, languages) in boxes.zip(slot_languages) {
+            let backend = Arc::clone(&self.backend);
+            let worker_id = Arc::clone(&worker_id);
+            let executor = self.isolate_executor.clone().with_box_id(box_id);
+            let scheduler = Arc::clone(&self.scheduler);
+            let load = self.load.clone();
+            let shutdown = shutdown.clone();
+            let starvation_timeout = self.starvation_timeout;
+            let result_cache_ttl = self.result_cache_ttl;
             
             let handle = task::spawn(async move {
-                loop {
-                    match redis.get_job_from_queue::<Job>("jobs").await {
+                while !*shutdown.borrow() {
+                    let languages = load.healthy_languages(&languages);
+                    let queues = match languages.is_empty() {
+                        true => Vec::new(),
+                        false => scheduler.next_queues(&load.running_by_tenant(), &languages),
+                    };
+                    if queues.is_empty() {
+                        // Every language failed its canary, or every tenant
+                        // is at its concurrency cap
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        continue;
+                    }
+                    let popped = backend.get_job_from_queue(&worker_id, &queues, starvation_timeout).await;
+                    let key = match &popped {
+                        Ok(Some(job)) => job.id.to_string(),
+                        _ => String::new(),
+                    };
+                    match popped {
+                        Ok(Some(job)) if !languages.contains(&job.language.name) => {
+                            // Its language started failing its canary here
+                            // since the queues were picked; put it back where
+                            // it was for other workers. Left unacked on error,
+                            // so the heartbeat puts it back instead.
+                            let keys = [key];
+                            if let Err(e) = backend.requeue_processing(&worker_id, Some(&keys)).await {
+                                eprintln!("Error requeueing job {}: {:?}", job.id, e);
+                            }
+                            continue;
+                        },
+                        Ok(Some(mut job)) if job.is_expired(unix_millis()) => {
+                            println!("Job {} passed its deadline in the queue", job.id);
+                            job.timings.dequeued_at = Some(unix_millis());
+                            if let Err(e) = executor.expire(&mut job).await {
+                                eprintln!("Error expiring job {}: {:?}", job.id, e);
+                            }
+                            metrics().record_verdict(&job.language.name, &job.status);
+                            coalesce::settle(backend.as_ref(), &executor, &job, result_cache_ttl).await;
+                            load.done.fetch_add(1, Ordering::Relaxed);
+                        },
                         Ok(Some(mut job)) => {
-                            
+                            let dequeued_at = SystemTime::now();
+                            let started_at = dequeued_at
+                                .duration_since(UNIX_EPOCH)
+                                .unwrap()
+                                .as_secs() as i64;
+                            job.timings.dequeued_at = Some(unix_millis());
+                            // Jobs queued before timings existed only have created_at
+                            let queued_at = UNIX_EPOCH
+                                + job.timings.queued_at.map_or(
+                                    Duration::from_secs(job.created_at.max(0) as u64),
+                                    |ms| Duration::from_millis(ms.max(0) as u64),
+                                );
+                            let queue_wait = dequeued_at.duration_since(queued_at).unwrap_or_default();
+                            telemetry::record_span("queue wait", &job.trace_context, queued_at, dequeued_at);
+                            let span = tracing::info_span!("job", job_id = job.id, language = %job.language.name);
+                            telemetry::resume(&span, &job.trace_context);
+
+                            metrics()
+                                .queue_wait
+                                .with_label_values(&[&job.language.name])
+                                .observe(queue_wait.as_secs_f64());
+                            metrics().jobs_in_flight.inc();
+                            scheduler.charge(&job.tenant, &job.language.name, languages.len());
+                            load.active.lock().unwrap().insert(
+                                job.id,
+                                ActiveJob {
+                                    id: job.id,
+                                    language: job.language.name.clone(),
+                                    tenant: job.tenant.clone(),
+                                    box_id,
+                                    started_at,
+                                },
+                            );
+
                             let max_retries = 3;
                             let mut retry_count = 0;
                             
-                            loop {
-                                let result = executor.execute(&mut job).await;
+                            let stored = loop {
+                                if retry_count > 0 && job.is_expired(unix_millis()) {
+                                    println!("Job {} passed its deadline between attempts", job.id);
+                                    match executor.expire(&mut job).await {
+                                        Ok(()) => {
+                                            metrics().record_verdict(&job.language.name, &job.status);
+                                            break true;
+                                        }
+                                        Err(e) => {
+                                            eprintln!("Error expiring job {}: {:?}", job.id, e);
+                                            break false;
+                                        }
+                                    }
+                                }
+                                let result = executor.execute(&mut job).instrument(span.clone()).await;
 
                                 match result {
                                     Ok(_) => {
-                                        cleanup_job(job.id).await;
-                                        break;
+                                        metrics().record_verdict(&job.language.name, &job.status);
+                                        cleanup_box(box_id).await;
+                                        break true;
                                     }
                                     Err(e) => {
                                         println!("Job {} failed: {:?}", job.id, e);
                                         retry_count += 1;
-                                        cleanup_job(job.id).await;
+                                        cleanup_box(box_id).await;
                                         if retry_count >= max_retries {
                                             println!("Job {} failed after {} retries", job.id, max_retries);
-                                            break;
+                                            let message = format!("Failed after {} attempts: {}", max_retries, e);
+                                            match executor.fail(&mut job, message).await {
+                                                Ok(()) => {
+                                                    metrics().record_verdict(&job.language.name, &job.status);
+                                                    break true;
+                                                }
+                                                Err(e) => {
+                                                    eprintln!("Error failing job {}: {:?}", job.id, e);
+                                                    break false;
+                                                }
+                                            }
                                         }
                                     }
                                 }
+                            };
+                            load.active.lock().unwrap().remove(&job.id);
+                            metrics().jobs_in_flight.dec();
+                            if !stored {
+                                // Without a stored result there is nothing to
+                                // give waiters; left unacked, the stray
+                                // requeue runs it again
+                                continue;
                             }
+                            coalesce::settle(backend.as_ref(), &executor, &job, result_cache_ttl).await;
+                            load.done.fetch_add(1, Ordering::Relaxed);
                         },
                         Ok(None) => {
                             tokio::time::sleep(Duration::from_millis(100)).await;
+                            continue;
                         },
                         Err(e) => {
                             eprintln!("Error fetching job from queue: {:?}", e);
                             tokio::time::sleep(Duration::from_secs(1)).await;
+                            continue;
                         }
                     }
+                    // Requeued or finished; a failed ack leaves a finished
+                    // job, which is skipped if it is ever requeued
+                    if let Err(e) = backend.ack_job(&worker_id, &key).await {
+                        eprintln!("Error acking job {}: {:?}", key, e);
+                    }
                 }
             });
             
             handles.push(handle);
         }
         
-        // Wait for all worker tasks
-        for handle in handles {
-            if let Err(e) = handle.await {
-                eprintln!("Worker task failed: {:?}", e);
+        // Wait for all worker tasks, up to the drain timeout after shutdown
+        let aborts: Vec<_> = handles.iter().map(|handle| handle.abort_handle()).collect();
+        let deadline = async {
+            let _ = shutdown.wait_for(|stop| *stop).await;
+            tokio::time::sleep(self.drain_timeout).await;
+        };
+        let mut finished = join_all(handles);
+        tokio::select! {
+            results = &mut finished => {
+                for result in results {
+                    if let Err(e) = result {
+                        eprintln!("Worker task failed: {:?}", e);
+                    }
+                }
+            }
+            _ = deadline => {
+                for abort in aborts {
+                    abort.abort();
+                }
+                // An aborted slot may still be inside a box operation; its
+                // box is only cleaned once the task is gone
+                let _ = finished.await;
+                self.requeue_active(&worker_id).await;
+            }
+        }
+
+        // A canary cut short leaves its box behind
+        if let Some(canaries) = canaries {
+            canaries.abort();
+            if canaries.await.is_err() {
+                cleanup_box(canary_box).await;
+            }
+        }
+
+        let _ = stop_heartbeat.send(true);
+        let _ = heartbeat.await;
+        Ok(())
+    }
+
+    /// Puts the jobs this worker abandoned back at the front of their
+    /// queues and cleans up their boxes. Called once the slots that ran
+    /// them have been aborted and awaited.
+    async fn requeue_active(&self, worker_id: &str) {
+        let abandoned: Vec<ActiveJob> = self.load.active.lock().unwrap().drain().map(|(_, job)| job).collect();
+        println!("Drain timeout passed, requeueing {} running jobs", abandoned.len());
+
+        let keys: Vec<String> = abandoned.iter().map(|active| active.id.to_string()).collect();
+        if let Err(e) = self.backend.requeue_processing(worker_id, Some(&keys)).await {
+            // Left in the processing set, where the heartbeat's last beat finds them
+            eprintln!("Error requeueing abandoned jobs: {:?}", e);
+        }
+        for active in abandoned {
+            cleanup_box(active.box_id).await;
+        }
+    }
+
+    /// Moves jobs queued before jobs were routed by language to their
+    /// languages' queues, so slots only ever take jobs they can run.
+    async fn route_legacy_queues(&self) {
+        for queue in self.scheduler.tenants().legacy_queues() {
+            if let Err(e) = self.backend.route_by_language(&queue, &Language::NAMES).await {
+                eprintln!("Error routing jobs in {} by language: {:?}", queue, e);
+            }
+        }
+    }
+
+    /// Cleans up boxes in this worker's range that a previous run left
+    /// initialized, since `--init` on them misbehaves.
+    async fn clean_stale_boxes(&self, boxes: Range<u64>) {
+        let mut entries = match fs::read_dir(&self.box_root).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+            Err(e) => {
+                eprintln!("Failed to list boxes in {}: {}", self.box_root, e);
+                return;
+            }
+        };
+        let mut stale = Vec::new();
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            if let Some(box_id) = entry.file_name().to_str().and_then(|name| name.parse().ok()) {
+                if boxes.contains(&box_id) {
+                    stale.push(box_id);
+                }
             }
         }
+
+        for box_id in stale {
+            println!("Cleaning up stale box {}", box_id);
+            cleanup_box(box_id).await;
+        }
     }
 }
 
-async fn cleanup_job(job_id: u64) {
-    let box_id = job_id % 2147483647;
-    println!("cleaning {}", box_id);
+/// Locks a file per box in `boxes`, next to the boxes in `box_root`, until
+/// the files are dropped or the process exits however it exits. A second
+/// worker given an overlapping range, or `flash run` given a box a worker
+/// uses, fails here instead of cleaning up boxes that are in use.
+pub async fn lock_boxes(box_root: &str, boxes: Range<u64>) -> Result<Vec<std::fs::File>, String> {
+    let box_root = PathBuf::from(box_root);
+    task::spawn_blocking(move || {
+        std::fs::create_dir_all(&box_root)
+            .map_err(|e| format!("Failed to create {}: {}", box_root.display(), e))?;
+        let mut locks = Vec::with_capacity(boxes.clone().count());
+        for box_id in boxes {
+            let path = box_root.join(format!("flash-{}.lock", box_id));
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&path)
+                .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+            match file.try_lock() {
+                Ok(()) => locks.push(file),
+                Err(TryLockError::WouldBlock) => {
+                    return Err(format!(
+                        "Box {} is used by another flash process on this host; give each worker its own range with --first-box, and flash run a box outside them with --box",
+                        box_id
+                    ))
+                }
+                Err(TryLockError::Error(e)) => return Err(format!("Failed to lock {}: {}", path.display(), e)),
+            }
+        }
+        Ok(locks)
+    })
+    .await
+    .expect("Locking boxes panicked")
+}
+
+/// Removes an isolate box and kills anything still running in it.
+pub async fn cleanup_box(box_id: u64) {
     if let Err(e) = Command::new("isolate")
-        .args(&["--cg", "-b", &box_id.to_string(), "--cleanup"])
+        .args(["--cg", "-b", &box_id.to_string(), "--cleanup"])
         .output()
+        .await
     {
         eprintln!("Failed to cleanup isolate box {}: {:?}", box_id, e);
     }
@@ -0,0 +1,184 @@
+use crate::{
+    client::{Backend, StoreResult},
+    core::{ActiveJob, CanaryResult, WorkerInfo},
+};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::sync::watch;
+
+/// What a worker's job slots share with its heartbeat.
+#[derive(Clone, Default)]
+pub struct WorkerLoad {
+    /// Jobs this worker is running, by job id.
+    pub active: Arc<Mutex<HashMap<u64, ActiveJob>>>,
+    /// Jobs this worker has finished since it started.
+    pub done: Arc<AtomicU64>,
+    /// Jobs other workers are running, by tenant, as of their last heartbeats.
+    pub fleet: Arc<Mutex<HashMap<String, usize>>>,
+    /// Latest canary result of each language, by language.
+    pub canaries: Arc<Mutex<BTreeMap<String, CanaryResult>>>,
+}
+
+impl WorkerLoad {
+    /// Jobs running across the fleet by tenant, counting this worker's
+    /// current jobs.
+    pub fn running_by_tenant(&self) -> HashMap<String, usize> {
+        let mut running = self.fleet.lock().unwrap().clone();
+        for job in self.active.lock().unwrap().values() {
+            *running.entry(job.tenant.clone()).or_default() += 1;
+        }
+        running
+    }
+
+    /// The languages of `languages` that haven't failed their last canary.
+    pub fn healthy_languages(&self, languages: &[String]) -> Vec<String> {
+        let canaries = self.canaries.lock().unwrap();
+        languages
+            .iter()
+            .filter(|language| canaries.get(*language).is_none_or(|canary| canary.healthy))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Heartbeats `worker` with its active and finished jobs every `interval` until `stop`
+/// turns true, then requeues anything left in its processing set and removes
+/// its record. Each beat also requeues the jobs of workers that have been
+/// silent for longer than `ttl` and this worker's stray jobs, and counts the
+/// jobs the others are running.
+pub async fn run_heartbeat(
+    backend: Arc<dyn Backend>,
+    mut worker: WorkerInfo,
+    load: WorkerLoad,
+    interval: Duration,
+    ttl: Duration,
+    mut stop: watch::Receiver<bool>,
+) {
+    let mut strays = HashSet::new();
+    loop {
+        let jobs = load.active.lock().unwrap().values().cloned().collect();
+        worker.canaries = load.canaries.lock().unwrap().clone();
+        worker.beat(jobs, load.done.load(Ordering::Relaxed));
+        if let Err(e) = backend.heartbeat(&worker).await {
+            tracing::error!("Error sending heartbeat for worker {}: {}", worker.id, e);
+        }
+
+        match backend.list_workers().await {
+            Ok(workers) => {
+                let mut fleet = HashMap::new();
+                let others = workers
+                    .iter()
+                    .filter(|other| other.id != worker.id && other.is_alive(ttl));
+                for job in others.flat_map(|other| &other.jobs) {
+                    *fleet.entry(job.tenant.clone()).or_default() += 1;
+                }
+                *load.fleet.lock().unwrap() = fleet;
+            }
+            Err(e) => tracing::error!("Error listing workers: {}", e),
+        }
+
+        requeue_strays(backend.as_ref(), &worker.id, &load, &mut strays).await;
+
+        match reap_dead_workers(backend.as_ref(), ttl).await {
+            Ok(0) => {}
+            Ok(requeued) => tracing::info!("Requeued {} jobs from dead workers", requeued),
+            Err(e) => tracing::error!("Error reaping dead workers: {}", e),
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = stop.wait_for(|stop| *stop) => break,
+        }
+    }
+
+    match backend.requeue_processing(&worker.id, None).await {
+        Ok(0) => {}
+        Ok(requeued) => tracing::warn!("Requeued {} jobs worker {} still held", requeued, worker.id),
+        Err(e) => {
+            // Keeps the record, so another worker reaps the jobs once it expires
+            tracing::error!("Error requeueing jobs of worker {}: {}", worker.id, e);
+            return;
+        }
+    }
+    if let Err(e) = backend.remove_worker(&worker.id).await {
+        tracing::error!("Error deregistering worker {}: {}", worker.id, e);
+    }
+}
+
+/// Requeues the jobs in `worker`'s processing set that no slot is running
+/// once they have been seen so on two beats in a row. A pop whose reply
+/// was lost, such as one that came after the command timed out, leaves its
+/// job there; a slot that has only just popped its job is seen once at
+/// most. `strays` carries the jobs seen on the previous beat.
+async fn requeue_strays(
+    backend: &dyn Backend,
+    worker: &str,
+    load: &WorkerLoad,
+    strays: &mut HashSet<String>,
+) {
+    let held = match backend.processing_jobs(worker).await {
+        Ok(held) => held,
+        Err(e) => {
+            tracing::error!("Error listing jobs held by worker {}: {}", worker, e);
+            return;
+        }
+    };
+    let running: HashSet<String> = load.active.lock().unwrap().keys().map(u64::to_string).collect();
+    let (stale, fresh): (Vec<String>, Vec<String>) = held
+        .into_iter()
+        .filter(|key| !running.contains(key))
+        .partition(|key| strays.contains(key));
+    *strays = fresh.into_iter().collect();
+
+    if stale.is_empty() {
+        return;
+    }
+    match backend.requeue_processing(worker, Some(&stale)).await {
+        Ok(requeued) => tracing::warn!("Requeued {} jobs no slot of worker {} was running", requeued, worker),
+        Err(e) => {
+            tracing::error!("Error requeueing stray jobs of worker {}: {}", worker, e);
+            strays.extend(stale);
+        }
+    }
+}
+
+/// Puts the jobs in the processing sets of workers whose last heartbeat is
+/// older than `ttl` back at the front of their queues, then removes the
+/// workers. A worker whose jobs can't be requeued keeps its record, so the
+/// next beat tries again. Returns how many jobs were requeued.
+pub async fn reap_dead_workers(backend: &dyn Backend, ttl: Duration) -> StoreResult<usize> {
+    let mut requeued = 0;
+
+    for worker in backend.list_workers().await? {
+        if worker.is_alive(ttl) {
+            continue;
+        }
+        // Each entry moves once, however many workers reap at the same time
+        match backend.requeue_processing(&worker.id, None).await {
+            Ok(0) => {}
+            Ok(count) => {
+                tracing::warn!(
+                    "Worker {} on {} missed its heartbeat, requeued {} jobs",
+                    worker.id,
+                    worker.host,
+                    count
+                );
+                requeued += count;
+            }
+            Err(e) => {
+                tracing::error!("Error requeueing jobs of dead worker {}: {}", worker.id, e);
+                continue;
+            }
+        }
+        if let Err(e) = backend.remove_worker(&worker.id).await {
+            tracing::error!("Error removing dead worker {}: {}", worker.id, e);
+        }
+    }
+    Ok(requeued)
+}
@@ -0,0 +1,88 @@
+use crate::{
+    client::QueueChoice,
+    core::{queue_name, tenant::Tenants, Priority},
+};
+use std::{collections::HashMap, sync::Mutex};
+
+/// Shares a worker's slots between tenants by weight, with smooth weighted
+/// round robin: every pick adds each tenant's weight to its credit, the
+/// tenant with the most credit goes first, and the tenant served is charged
+/// the total weight. Credit is capped at the total weight, so a tenant that
+/// was idle gets at most one extra turn when its jobs arrive. Languages take
+/// turns the same way, all with weight 1.
+pub struct FairScheduler {
+    tenants: Tenants,
+    credit: Mutex<HashMap<String, i64>>,
+    language_credit: Mutex<HashMap<String, i64>>,
+}
+
+/// Adds each of `weights` to its credit, capped at their total, and returns
+/// them with the most credit first, by name among equals.
+fn take_turns<'a>(credit: &mut HashMap<String, i64>, weights: &[(&'a str, i64)]) -> Vec<&'a str> {
+    let total: i64 = weights.iter().map(|(_, weight)| weight).sum();
+    let mut order: Vec<(&str, i64)> = weights
+        .iter()
+        .map(|(name, weight)| {
+            let credit = credit.entry(name.to_string()).or_default();
+            *credit = (*credit + weight).min(total);
+            (*name, *credit)
+        })
+        .collect();
+    order.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    order.into_iter().map(|(name, _)| name).collect()
+}
+
+impl FairScheduler {
+    pub fn new(tenants: Tenants) -> Self {
+        Self {
+            tenants,
+            credit: Mutex::new(HashMap::new()),
+            language_credit: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn tenants(&self) -> &Tenants {
+        &self.tenants
+    }
+
+    /// Queues to take the next job in one of `languages` from, in order: by
+    /// priority, then by tenant in turn, then by language in turn.
+    /// Tenants that have `running` jobs up to their concurrency cap are
+    /// left out. The caps count other workers' jobs as of their last
+    /// heartbeats, so the fleet can briefly run more than a cap allows.
+    pub fn next_queues(&self, running: &HashMap<String, usize>, languages: &[String]) -> Vec<QueueChoice> {
+        let eligible: Vec<(&str, i64)> = self
+            .tenants
+            .names()
+            .filter(|tenant| {
+                self.tenants
+                    .max_concurrency(tenant)
+                    .is_none_or(|max| running.get(*tenant).copied().unwrap_or(0) < max)
+            })
+            .map(|tenant| (tenant, self.tenants.weight(tenant) as i64))
+            .collect();
+        let order = take_turns(&mut self.credit.lock().unwrap(), &eligible);
+
+        let languages: Vec<(&str, i64)> = languages.iter().map(|language| (language.as_str(), 1)).collect();
+        let languages = take_turns(&mut self.language_credit.lock().unwrap(), &languages);
+
+        Priority::ALL
+            .iter()
+            .flat_map(|priority| order.iter().enumerate().map(move |(turn, tenant)| (priority, turn, *tenant)))
+            .flat_map(|(priority, turn, tenant)| {
+                languages.iter().map(move |language| QueueChoice {
+                    name: queue_name(*priority, tenant, language),
+                    turn,
+                })
+            })
+            .collect()
+    }
+
+    /// Charges `tenant` and `language` for a job taken from their queue.
+    /// `languages` is how many languages took part in the pick.
+    pub fn charge(&self, tenant: &str, language: &str, languages: usize) {
+        let total: i64 = self.tenants.names().map(|tenant| self.tenants.weight(tenant) as i64).sum();
+        *self.credit.lock().unwrap().entry(tenant.to_string()).or_default() -= total;
+        *self.language_credit.lock().unwrap().entry(language.to_string()).or_default() -= languages as i64;
+    }
+}
@@ -0,0 +1,89 @@
+use crate::{
+    client::{archive::JobArchive, Backend, JobStore},
+    core::RetentionSettings,
+    utils::utils::run_waiters,
+};
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Periodically drops index entries for expired results, enforces the
+/// archive retention window and queues jobs left waiting on a result cache
+/// run that was lost. Runs until the task is aborted.
+pub async fn run_sweeper(
+    store: Arc<dyn Backend>,
+    archive: Option<JobArchive>,
+    retention: RetentionSettings,
+) {
+    let mut interval = tokio::time::interval(retention.sweep_interval);
+    loop {
+        interval.tick().await;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        if let Some(ttl) = retention.result_ttl {
+            match store.sweep_expired(now - ttl.as_secs() as i64).await {
+                Ok(0) => {}
+                Ok(removed) => tracing::info!("Swept {} expired job index entries", removed),
+                Err(e) => tracing::error!("Error sweeping expired jobs: {}", e),
+            }
+        }
+
+        if let (Some(archive), Some(retention)) = (&archive, retention.archive_retention) {
+            match archive.delete_finished_before(now - retention.as_secs() as i64).await {
+                Ok(0) => {}
+                Ok(removed) => tracing::info!("Deleted {} jobs past archive retention", removed),
+                Err(e) => tracing::error!("Error enforcing archive retention: {}", e),
+            }
+        }
+
+        match store.take_orphans().await {
+            Ok(orphans) if orphans.is_empty() => {}
+            Ok(orphans) => {
+                tracing::warn!("Queueing {} jobs whose identical run was lost", orphans.len());
+                run_waiters(store.as_ref(), orphans).await;
+            }
+            Err(e) => tracing::error!("Error collecting jobs waiting on lost runs: {}", e),
+        }
+    }
+}
+
+/// Copies finished jobs from the store into the archive. Workers on other
+/// hosts can't write to this node's archive, so the node serving the API
+/// picks their results up from the finished index instead.
+pub async fn run_archiver(store: Arc<dyn JobStore>, archive: JobArchive, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+    // Archiving is idempotent, so start from the beginning of the index
+    // rather than risk missing jobs that finished while this node was down.
+    let mut since = 0;
+    loop {
+        interval.tick().await;
+        let finished = match store.finished_since(since).await {
+            Ok(finished) => finished,
+            Err(e) => {
+                tracing::error!("Error listing finished jobs: {}", e);
+                continue;
+            }
+        };
+
+        for (key, finished_at) in finished {
+            match store.get_job(&key).await {
+                Ok(Some(job)) => {
+                    if let Err(e) = archive.archive_job(&job).await {
+                        tracing::error!("Error archiving job {}: {}", key, e);
+                        break;
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    tracing::error!("Error loading finished job {}: {}", key, e);
+                    break;
+                }
+            }
+            since = since.max(finished_at);
+        }
+    }
+}